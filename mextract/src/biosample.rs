@@ -0,0 +1,192 @@
+use xmltree::Element;
+
+use super::decode::LabelDecoder;
+use super::ena::{parse_date, EnaInfo};
+use super::{get_child_text, parse_fields, Attr, MyResult, Sample, Xref};
+
+// --------------------------------------------------
+/// Maps an NCBI BioSample into the same record as an ENA SAMPLE; the
+/// SRA sample accession (SRS/ERS) is preferred as the accession so that
+/// records match those from ENA
+pub fn parse_biosample(root: &Element, decoders: &[Box<dyn LabelDecoder>]) -> MyResult<Sample> {
+    let biosample = root.attributes.get("accession").cloned();
+    let ids = match root.get_child("Ids") {
+        Some(ids) => ids,
+        _ => return Err(From::from("Missing Ids")),
+    };
+
+    let id_for = |db: &str| {
+        ids.children
+            .iter()
+            .find(|id| id.attributes.get("db").map(|d| d.as_str()) == Some(db))
+            .and_then(|id| id.text.clone())
+    };
+
+    let biosample = biosample.or_else(|| id_for("BioSample"));
+    let accession = match id_for("SRA").or_else(|| biosample.clone()) {
+        Some(acc) => acc,
+        _ => return Err(From::from("Missing BioSample or SRA Id")),
+    };
+
+    let attributes = match root.get_child("Attributes") {
+        Some(attrs) => get_biosample_attributes(attrs),
+        _ => return Err(From::from("Missing Attributes")),
+    };
+
+    let identifiers = ids
+        .children
+        .iter()
+        .filter_map(|id| {
+            let db = id.attributes.get("db")?;
+            id.text.as_ref().map(|text| Xref {
+                db: db.to_string(),
+                id: text.trim().to_string(),
+            })
+        })
+        .collect();
+
+    let links: Vec<Xref> = root
+        .get_child("Links")
+        .map(|links| {
+            links
+                .children
+                .iter()
+                .filter_map(|l| {
+                    let db = l
+                        .attributes
+                        .get("target")
+                        .or_else(|| l.attributes.get("type"))?;
+                    l.attributes
+                        .get("label")
+                        .cloned()
+                        .or_else(|| l.text.clone())
+                        .map(|id| Xref {
+                            db: db.to_string(),
+                            id,
+                        })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let studies = links
+        .iter()
+        .filter(|l| l.db == "bioproject")
+        .map(|l| l.id.to_string())
+        .collect();
+
+    let desc = root.get_child("Description");
+    let organism = desc.and_then(|desc| desc.get_child("Organism"));
+    let mut sample = Sample {
+        accession,
+        biosample,
+        title: desc.and_then(|desc| get_child_text(desc, "Title")),
+        description: desc
+            .and_then(|desc| desc.get_child("Comment"))
+            .and_then(|comment| get_child_text(comment, "Paragraph")),
+        taxon_id: organism.and_then(|org| org.attributes.get("taxonomy_id").cloned()),
+        scientific_name: organism.and_then(|org| {
+            org.attributes
+                .get("taxonomy_name")
+                .cloned()
+                .or_else(|| get_child_text(org, "OrganismName"))
+        }),
+        common_name: organism.and_then(|org| get_child_text(org, "OrganismCommonName")),
+        identifiers,
+        links,
+        studies,
+        attributes,
+        ena: EnaInfo {
+            first_public: root
+                .attributes
+                .get("publication_date")
+                .and_then(|d| parse_date(d)),
+            last_update: root
+                .attributes
+                .get("last_update")
+                .and_then(|d| parse_date(d)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    parse_fields(&mut sample, decoders);
+
+    Ok(sample)
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn get_biosample_attributes(attributes: &Element) -> Vec<Attr> {
+    attributes
+        .children
+        .iter()
+        .enumerate()
+        .filter_map(|(i, attr)| {
+            let harmonized_name = attr.attributes.get("harmonized_name").cloned();
+            let tag = attr
+                .attributes
+                .get("attribute_name")
+                .cloned()
+                .or_else(|| harmonized_name.clone())?;
+
+            Some(Attr {
+                tag,
+                value: attr.text.clone().unwrap_or_default(),
+                units: attr.attributes.get("units").cloned(),
+                harmonized_name,
+                position: i + 1,
+            })
+        })
+        .collect()
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_parse_biosample() {
+    let root = Element::parse(::std::fs::File::open("test/biosample.xml").unwrap()).unwrap();
+    let sample = parse_biosample(&root.children[0], &[]).unwrap();
+    assert_eq!(sample.accession, "SRS1234567");
+    assert_eq!(sample.biosample, Some("SAMN04567890".to_string()));
+    assert_eq!(sample.taxon_id, Some("408172".to_string()));
+    assert_eq!(
+        sample.scientific_name,
+        Some("marine metagenome".to_string())
+    );
+    assert_eq!(sample.studies, vec!["PRJNA312345".to_string()]);
+    assert!(sample.identifiers.contains(&Xref {
+        db: "SRA".to_string(),
+        id: "SRS1234567".to_string()
+    }));
+    assert_eq!(sample.depth, Some(0.1));
+    assert_eq!(sample.ena.first_public, parse_date("2016-03-01"));
+    assert_eq!(sample.ena.last_update, parse_date("2016-03-04"));
+    assert_eq!(sample.lat_lon, Some((32.8794, -117.2539)));
+
+    // the harmonized collection_date is trusted over a tag name it
+    // would not otherwise recognize
+    let dates: Vec<&str> = sample
+        .dates
+        .iter()
+        .filter(|d| d.tag_ok)
+        .map(|d| d.tag.as_str())
+        .collect();
+    assert_eq!(dates, vec!["sampling day"]);
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_biosample_no_ids() {
+    let xml = r#"
+    <BioSample accession="SAMN04567890">
+        <Attributes>
+            <Attribute attribute_name="depth" harmonized_name="depth">5 m</Attribute>
+        </Attributes>
+    </BioSample>
+    "#;
+
+    let root = Element::parse(xml.as_bytes()).unwrap();
+    assert!(parse_biosample(&root, &[]).is_err());
+}
@@ -0,0 +1,450 @@
+use regex::Regex;
+use std::collections::HashMap;
+use xmltree::Element;
+
+use super::{get_child_text, read_sets, set_members, Attr, MyResult, Sample};
+
+/// INSDC missing-value terms, accepted for any field
+const MISSING_VALUES: &[&str] = &[
+    "not applicable",
+    "not collected",
+    "not provided",
+    "restricted access",
+    "missing: control sample",
+    "missing: sample group",
+    "missing: synthetic construct",
+    "missing: lab stock",
+    "missing: third party data",
+    "missing: data agreement established pre-2023",
+    "missing: endangered species",
+    "missing: human-identifiable",
+];
+
+#[derive(Debug)]
+pub struct ValidateConfig {
+    pub checklists: Vec<String>,
+    pub default_checklist: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Requirement {
+    Mandatory,
+    Recommended,
+    Optional,
+}
+
+/// One FIELD of an ENA checklist
+#[derive(Debug)]
+pub struct ChecklistField {
+    pub name: String,
+    pub requirement: Requirement,
+    pub regex: Option<Regex>,
+    pub choices: Vec<String>,
+    pub units: Vec<String>,
+    pub multiple: bool,
+}
+
+#[derive(Debug)]
+pub struct Checklist {
+    pub accession: String,
+    pub label: Option<String>,
+    pub fields: Vec<ChecklistField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub field: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// How one sample measures up to its checklist
+#[derive(Debug)]
+pub struct Report {
+    pub accession: String,
+    pub checklist: Option<String>,
+    pub checklist_label: Option<String>,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn count(&self, severity: Severity) -> usize {
+        self.issues
+            .iter()
+            .filter(|i| i.severity == severity)
+            .count()
+    }
+
+    pub fn is_compliant(&self) -> bool {
+        self.count(Severity::Error) == 0
+    }
+}
+
+// --------------------------------------------------
+/// Reads CHECKLIST_SET XML file(s) or a directory of them, by accession
+pub fn read_checklists(paths: &[String]) -> MyResult<HashMap<String, Checklist>> {
+    let mut checklists = HashMap::new();
+    for root in read_sets(paths)? {
+        for elem in set_members(&root, "CHECKLIST") {
+            let checklist = parse_checklist(elem)?;
+            checklists.insert(checklist.accession.to_string(), checklist);
+        }
+    }
+
+    if checklists.is_empty() {
+        return Err(From::from("No CHECKLIST found"));
+    }
+
+    Ok(checklists)
+}
+
+// --------------------------------------------------
+/// Checks the sample's attributes against the checklist it declares in
+/// ENA-CHECKLIST (or the default): mandatory fields missing or values
+/// outside the vocabulary, regex or units are errors, and missing
+/// recommended fields or units are warnings
+pub fn validate(
+    sample: &Sample,
+    checklists: &HashMap<String, Checklist>,
+    default_checklist: Option<&str>,
+) -> Report {
+    let accession = sample
+        .ena
+        .checklist
+        .as_deref()
+        .or(default_checklist)
+        .map(|acc| acc.to_string());
+    let mut report = Report {
+        accession: sample.accession.to_string(),
+        checklist: accession.clone(),
+        checklist_label: accession
+            .as_ref()
+            .and_then(|acc| checklists.get(acc))
+            .and_then(|checklist| checklist.label.clone()),
+        issues: vec![],
+    };
+    let mut issue = |field: &str, severity: Severity, message: String| {
+        report.issues.push(Issue {
+            field: field.to_string(),
+            severity,
+            message,
+        })
+    };
+
+    let checklist = match accession.as_ref().map(|acc| checklists.get(acc)) {
+        Some(Some(checklist)) => checklist,
+        Some(None) => {
+            issue(
+                "ENA-CHECKLIST",
+                Severity::Error,
+                format!("unknown checklist {}", accession.unwrap_or_default()),
+            );
+            return report;
+        }
+        None => {
+            issue(
+                "ENA-CHECKLIST",
+                Severity::Error,
+                "no checklist declared".to_string(),
+            );
+            return report;
+        }
+    };
+
+    for field in &checklist.fields {
+        let attrs: Vec<&Attr> = sample
+            .attributes
+            .iter()
+            .filter(|a| a.tag.trim().eq_ignore_ascii_case(&field.name))
+            .filter(|a| !a.value.trim().is_empty())
+            .collect();
+
+        if attrs.is_empty() {
+            match field.requirement {
+                Requirement::Mandatory => issue(
+                    &field.name,
+                    Severity::Error,
+                    "missing mandatory field".to_string(),
+                ),
+                Requirement::Recommended => issue(
+                    &field.name,
+                    Severity::Warning,
+                    "missing recommended field".to_string(),
+                ),
+                Requirement::Optional => (),
+            }
+            continue;
+        }
+
+        if attrs.len() > 1 && !field.multiple {
+            issue(
+                &field.name,
+                Severity::Error,
+                format!("given {} times but allowed once", attrs.len()),
+            );
+        }
+
+        for attr in attrs {
+            let value = attr.value.trim();
+            if MISSING_VALUES.contains(&value.to_lowercase().as_str()) {
+                continue;
+            }
+
+            if !field.choices.is_empty()
+                && !field.choices.iter().any(|c| c.eq_ignore_ascii_case(value))
+            {
+                issue(
+                    &field.name,
+                    Severity::Error,
+                    format!("\"{}\" is not one of {}", value, field.choices.join(", ")),
+                );
+            }
+
+            if let Some(re) = &field.regex {
+                if !re.is_match(value) {
+                    issue(
+                        &field.name,
+                        Severity::Error,
+                        format!("\"{}\" does not match /{}/", value, regex_source(re)),
+                    );
+                }
+            }
+
+            if !field.units.is_empty() {
+                match attr.units.as_deref().map(|u| u.trim()) {
+                    Some(units) if field.units.iter().any(|u| u.eq_ignore_ascii_case(units)) => (),
+                    Some(units) => issue(
+                        &field.name,
+                        Severity::Error,
+                        format!(
+                            "units \"{}\" are not one of {}",
+                            units,
+                            field.units.join(", ")
+                        ),
+                    ),
+                    None => issue(
+                        &field.name,
+                        Severity::Warning,
+                        format!("no units, expected one of {}", field.units.join(", ")),
+                    ),
+                }
+            }
+        }
+    }
+
+    report
+}
+
+// --------------------------------------------------
+/// Writes one tab-separated line per sample: its checklist, status,
+/// error and warning counts, and the issues
+pub fn print_reports(reports: &[Report]) {
+    println!("sample\tchecklist\tchecklist_label\tstatus\terrors\twarnings\tissues");
+    for report in reports {
+        let issues: Vec<String> = report
+            .issues
+            .iter()
+            .map(|i| {
+                let severity = match i.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                };
+                format!("{} {}: {}", severity, i.field, i.message)
+            })
+            .collect();
+
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            report.accession,
+            report.checklist.as_deref().unwrap_or("-"),
+            report.checklist_label.as_deref().unwrap_or("-"),
+            if report.is_compliant() {
+                "compliant"
+            } else {
+                "non-compliant"
+            },
+            report.count(Severity::Error),
+            report.count(Severity::Warning),
+            issues.join("; ")
+        );
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn parse_checklist(elem: &Element) -> MyResult<Checklist> {
+    let accession = match elem.attributes.get("accession") {
+        Some(acc) => acc.to_string(),
+        _ => super::get_primary_id(elem)?,
+    };
+
+    let desc = elem.get_child("DESCRIPTOR");
+    let mut fields = vec![];
+    for group in desc.iter().flat_map(|d| d.children.iter()) {
+        for field in group.children.iter().filter(|c| c.name == "FIELD") {
+            fields.push(parse_field(field, &accession)?);
+        }
+    }
+
+    Ok(Checklist {
+        accession,
+        label: desc.and_then(|d| get_child_text(d, "LABEL")),
+        fields,
+    })
+}
+
+// --------------------------------------------------
+fn parse_field(elem: &Element, accession: &str) -> MyResult<ChecklistField> {
+    let name = match get_child_text(elem, "NAME").or_else(|| get_child_text(elem, "LABEL")) {
+        Some(name) => name,
+        _ => return Err(From::from("Checklist FIELD has no NAME")),
+    };
+
+    let requirement = match get_child_text(elem, "MANDATORY").as_deref() {
+        Some("mandatory") => Requirement::Mandatory,
+        Some("recommended") => Requirement::Recommended,
+        _ => Requirement::Optional,
+    };
+
+    let field_type = elem
+        .get_child("FIELD_TYPE")
+        .and_then(|t| t.children.first());
+
+    // Some ENA patterns use syntax the regex crate lacks; those are skipped
+    // with a note, as values of the field go unchecked
+    let regex = field_type
+        .and_then(|t| get_child_text(t, "REGEX_VALUE"))
+        .and_then(|re| match Regex::new(&format!("^(?:{})$", re)) {
+            Ok(regex) => Some(regex),
+            _ => {
+                eprintln!(
+                    "Cannot check {} \"{}\" against unsupported pattern /{}/",
+                    accession, name, re
+                );
+                None
+            }
+        });
+
+    let choices = field_type
+        .filter(|t| t.name == "TEXT_CHOICE_FIELD")
+        .map(|t| {
+            t.children
+                .iter()
+                .filter_map(|v| get_child_text(v, "VALUE"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let units = elem
+        .get_child("UNITS")
+        .map(|u| u.children.iter().filter_map(|u| u.text.clone()).collect())
+        .unwrap_or_default();
+
+    Ok(ChecklistField {
+        name,
+        requirement,
+        regex,
+        choices,
+        units,
+        multiple: get_child_text(elem, "MULTIPLICITY").as_deref() == Some("multiple"),
+    })
+}
+
+// --------------------------------------------------
+/// The checklist's pattern without the anchors added when compiling it
+fn regex_source(re: &Regex) -> &str {
+    re.as_str()
+        .strip_prefix("^(?:")
+        .and_then(|re| re.strip_suffix(")$"))
+        .unwrap_or(re.as_str())
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_read_checklists() {
+    let checklists = read_checklists(&["test/checklists".to_string()]).unwrap();
+    let checklist = &checklists["ERC000030"];
+    assert_eq!(checklist.label, Some("ENA Tara Oceans".to_string()));
+    assert_eq!(checklist.fields.len(), 16);
+
+    let platform = &checklist.fields[5];
+    assert_eq!(platform.name, "Sampling Platform");
+    assert_eq!(platform.requirement, Requirement::Mandatory);
+    assert_eq!(platform.choices, vec!["SV Tara", "Tara"]);
+
+    let depth = &checklist.fields[4];
+    assert_eq!(depth.units, vec!["m"]);
+    assert!(depth.regex.as_ref().unwrap().is_match("5"));
+    assert!(!depth.regex.as_ref().unwrap().is_match("5 m"));
+
+    // a lookahead does not compile, so the field goes unchecked
+    let campaign = checklist.fields.last().unwrap();
+    assert_eq!(campaign.name, "Sampling Campaign");
+    assert!(campaign.regex.is_none());
+}
+
+// --------------------------------------------------
+#[test]
+fn test_validate() {
+    let checklists = read_checklists(&["test/checklists".to_string()]).unwrap();
+    let mut sample = super::tara_sample();
+
+    let report = validate(&sample, &checklists, None);
+    assert_eq!(report.checklist, Some("ERC000030".to_string()));
+    assert_eq!(report.checklist_label, Some("ENA Tara Oceans".to_string()));
+    assert!(!report.is_compliant());
+    let issues: Vec<(&str, Severity)> = report
+        .issues
+        .iter()
+        .map(|i| (i.field.as_str(), i.severity))
+        .collect();
+    assert_eq!(
+        issues,
+        vec![
+            ("Environment (Material)", Severity::Error),
+            ("Sampling Site", Severity::Warning),
+            ("Size Fraction Lower Threshold", Severity::Warning),
+            ("Nitrate Sensor", Severity::Error),
+        ]
+    );
+
+    // Fix the errors, and drop a mandatory field but say why
+    for attr in sample.attributes.iter_mut() {
+        match attr.tag.as_str() {
+            "Environment (Material)" => {
+                attr.value = "plankton material (ENVO:01000277)".to_string()
+            }
+            "Nitrate Sensor" => attr.units = Some("µmol/kg".to_string()),
+            "Protocol Label" => attr.value = "not provided".to_string(),
+            "Sampling Platform" => attr.value = "RV Polarstern".to_string(),
+            _ => (),
+        }
+    }
+    let report = validate(&sample, &checklists, None);
+    assert_eq!(report.count(Severity::Error), 1);
+    assert_eq!(
+        report.issues[0].message,
+        "\"RV Polarstern\" is not one of SV Tara, Tara"
+    );
+
+    sample.attributes.retain(|a| a.tag != "Sampling Platform");
+    let report = validate(&sample, &checklists, None);
+    assert_eq!(report.issues[0].message, "missing mandatory field");
+
+    sample.ena.checklist = None;
+    assert_eq!(
+        validate(&sample, &checklists, None).issues[0].message,
+        "no checklist declared"
+    );
+    let report = validate(&sample, &checklists, Some("ERC000011"));
+    assert_eq!(report.issues[0].message, "unknown checklist ERC000011");
+}
@@ -0,0 +1,224 @@
+use arrow_array::builder::{
+    Float64Builder, ListBuilder, MapBuilder, StringBuilder, TimestampSecondBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use parquet::arrow::ArrowWriter;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::sync::Arc;
+
+use super::export::SampleWriter;
+use super::provenance::Provenance;
+use super::{MyResult, Sample};
+
+/// Samples buffered before they are written out as a row group
+const ROW_GROUP_SIZE: usize = 10_000;
+
+/// Streams samples into a Parquet file one row group at a time
+pub struct ParquetWriter {
+    writer: Option<ArrowWriter<File>>,
+    columns: Columns,
+}
+
+/// Builders for each column of the sample table
+struct Columns {
+    rows: usize,
+    accession: StringBuilder,
+    biosample: StringBuilder,
+    title: StringBuilder,
+    collection_date: TimestampSecondBuilder,
+    collection_date_precision: StringBuilder,
+    latitude: Float64Builder,
+    longitude: Float64Builder,
+    depth: Float64Builder,
+    runs: ListBuilder<StringBuilder>,
+    experiments: ListBuilder<StringBuilder>,
+    studies: ListBuilder<StringBuilder>,
+    attributes: MapBuilder<StringBuilder, StringBuilder>,
+}
+
+impl ParquetWriter {
+    pub fn open(path: &str, prov: &Provenance) -> MyResult<ParquetWriter> {
+        let mut columns = Columns::new();
+        let schema = columns.finish()?.schema();
+        let metadata = prov
+            .key_values()
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value))
+            .collect();
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .set_key_value_metadata(Some(metadata))
+            .build();
+
+        Ok(ParquetWriter {
+            writer: Some(ArrowWriter::try_new(
+                File::create(path)?,
+                schema,
+                Some(props),
+            )?),
+            columns,
+        })
+    }
+
+    fn flush(&mut self) -> MyResult<()> {
+        if self.columns.rows > 0 {
+            let batch = self.columns.finish()?;
+            if let Some(writer) = self.writer.as_mut() {
+                writer.write(&batch)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SampleWriter for ParquetWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        self.columns.append(sample)?;
+        if self.columns.rows >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        self.flush()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl Columns {
+    fn new() -> Columns {
+        Columns {
+            rows: 0,
+            accession: StringBuilder::new(),
+            biosample: StringBuilder::new(),
+            title: StringBuilder::new(),
+            collection_date: TimestampSecondBuilder::new().with_timezone("UTC"),
+            collection_date_precision: StringBuilder::new(),
+            latitude: Float64Builder::new(),
+            longitude: Float64Builder::new(),
+            depth: Float64Builder::new(),
+            runs: ListBuilder::new(StringBuilder::new()),
+            experiments: ListBuilder::new(StringBuilder::new()),
+            studies: ListBuilder::new(StringBuilder::new()),
+            attributes: MapBuilder::new(None, StringBuilder::new(), StringBuilder::new()),
+        }
+    }
+
+    fn append(&mut self, sample: &Sample) -> MyResult<()> {
+        let date = sample.collection_date();
+        self.accession.append_value(&sample.accession);
+        self.biosample.append_option(sample.biosample.as_ref());
+        self.title.append_option(sample.title.as_ref());
+        self.collection_date
+            .append_option(date.map(|d| d.value.timestamp()));
+        self.collection_date_precision
+            .append_option(date.map(|d| d.precision));
+        self.latitude
+            .append_option(sample.lat_lon.map(|(lat, _)| lat));
+        self.longitude
+            .append_option(sample.lat_lon.map(|(_, lon)| lon));
+        self.depth.append_option(sample.depth);
+        self.runs
+            .append_value(sample.runs.iter().map(|r| Some(r.as_str())));
+        self.experiments
+            .append_value(sample.experiments.iter().map(|e| Some(e.as_str())));
+        self.studies
+            .append_value(sample.studies.iter().map(|s| Some(s.as_str())));
+
+        for attr in &sample.attributes {
+            self.attributes.keys().append_value(&attr.tag);
+            self.attributes.values().append_value(&attr.value);
+        }
+        self.attributes.append(true)?;
+
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Takes the rows appended so far as a batch, resetting the builders
+    fn finish(&mut self) -> MyResult<RecordBatch> {
+        self.rows = 0;
+        let columns: Vec<(&str, ArrayRef, bool)> = vec![
+            ("accession", Arc::new(self.accession.finish()), false),
+            ("biosample", Arc::new(self.biosample.finish()), true),
+            ("title", Arc::new(self.title.finish()), true),
+            (
+                "collection_date",
+                Arc::new(self.collection_date.finish()),
+                true,
+            ),
+            (
+                "collection_date_precision",
+                Arc::new(self.collection_date_precision.finish()),
+                true,
+            ),
+            ("latitude", Arc::new(self.latitude.finish()), true),
+            ("longitude", Arc::new(self.longitude.finish()), true),
+            ("depth", Arc::new(self.depth.finish()), true),
+            ("runs", Arc::new(self.runs.finish()), true),
+            ("experiments", Arc::new(self.experiments.finish()), true),
+            ("studies", Arc::new(self.studies.finish()), true),
+            ("attributes", Arc::new(self.attributes.finish()), true),
+        ];
+
+        Ok(RecordBatch::try_from_iter_with_nullable(columns)?)
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_parquet_writer() {
+    use arrow_array::{Array, Float64Array, ListArray, TimestampSecondArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let root = ::xmltree::Element::parse(File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let sample = super::parse_xml(root, &[]).unwrap();
+    let path = ::std::env::temp_dir().join(format!("mextract-{}.parquet", ::std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let prov = super::provenance::get_provenance(&[]).unwrap();
+    let mut writer = ParquetWriter::open(path, &prov).unwrap();
+    writer.write(&sample).unwrap();
+    writer.write(&Sample::default()).unwrap();
+    writer.finish().unwrap();
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+    let metadata = builder
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .unwrap();
+    assert!(metadata.iter().any(|kv| kv.key == "ruleset_version"));
+
+    let mut reader = builder.build().unwrap();
+    let batch = reader.next().unwrap().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+
+    let lat = batch.column_by_name("latitude").unwrap();
+    let lat = lat.as_any().downcast_ref::<Float64Array>().unwrap();
+    assert_eq!(lat.value(0), 36.1715);
+    assert!(lat.is_null(1));
+
+    let date = batch.column_by_name("collection_date").unwrap();
+    let date = date
+        .as_any()
+        .downcast_ref::<TimestampSecondArray>()
+        .unwrap();
+    assert_eq!(
+        date.value_as_datetime(0).unwrap().to_string(),
+        "2012-03-09 08:59:00"
+    );
+
+    let runs = batch.column_by_name("runs").unwrap();
+    let runs = runs.as_any().downcast_ref::<ListArray>().unwrap();
+    assert_eq!(runs.value(0).len(), 4);
+    ::std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,262 @@
+use regex::Regex;
+use std::fmt::Debug;
+
+use super::fraction::PROTOCOL_CODE;
+use super::{cap_precision, cap_to_dt, parse_depth, Inferred, MyResult, PossibleDate, Sample};
+
+/// Tara Oceans labels, e.g.,
+/// "TARA_20120309T0859Z_151_EVENT_PUMP_P_S_(5 m)_PROT_NUC-RNA(100L)_W0.8-5",
+/// whose size fraction is read with the protocol code pattern
+const TARA_PATTERN: &str = r"(?x)
+    ^TARA_
+    (?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})
+    T(?P<hour>\d{2})(?P<minutes>\d{2})Z
+    _(?P<station>\d+)
+    (?:_EVENT_[^(]*[(](?P<depth>\d+(?:\.\d+)?\s*m)[)])?
+";
+
+/// The attribute that holds Tara-style event labels
+const EVENT_LABEL: &str = "Event Label";
+
+/// What a decoder found in a label
+#[derive(Debug, Default, PartialEq)]
+pub struct Decoded {
+    pub date: Option<PossibleDate>,
+    pub depth: Option<f64>,
+    pub station: Option<String>,
+    pub size_fraction: Option<String>,
+}
+
+/// Extracts metadata encoded in a sample's TITLE or "Event Label"
+pub trait LabelDecoder: Debug {
+    fn name(&self) -> &str;
+    fn decode(&self, label: &str) -> Option<Decoded>;
+}
+
+/// A regex with any of the named groups year, month, day, hour, minutes,
+/// seconds (as for dates elsewhere), depth, station and size
+#[derive(Debug)]
+pub struct RegexDecoder {
+    name: String,
+    re: Regex,
+}
+
+impl LabelDecoder for RegexDecoder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn decode(&self, label: &str) -> Option<Decoded> {
+        let caps = self.re.captures(label.trim())?;
+        let text = |name: &str| caps.name(name).map(|m| m.as_str().to_string());
+
+        Some(Decoded {
+            date: cap_to_dt(&caps).map(|value| PossibleDate {
+                tag: String::new(),
+                value,
+                precision: cap_precision(&caps),
+                tag_ok: true,
+            }),
+            depth: text("depth").and_then(|d| parse_depth(&d)),
+            station: text("station"),
+            size_fraction: text("size"),
+        })
+    }
+}
+
+// --------------------------------------------------
+/// The user's "NAME=REGEX" decoders, which are tried before the built-in
+/// Tara decoder
+pub fn get_decoders(specs: &[String]) -> MyResult<Vec<Box<dyn LabelDecoder>>> {
+    let mut decoders: Vec<Box<dyn LabelDecoder>> = vec![];
+    for spec in specs {
+        let (name, pattern) = match spec.find('=') {
+            Some(i) if i > 0 => (&spec[..i], &spec[i + 1..]),
+            _ => {
+                return Err(From::from(format!(
+                    "Decoder \"{}\" is not NAME=REGEX",
+                    spec
+                )))
+            }
+        };
+        let re = Regex::new(pattern)
+            .map_err(|e| format!("Decoder \"{}\" has a bad regex: {}", name, e))?;
+        decoders.push(Box::new(RegexDecoder {
+            name: name.to_string(),
+            re,
+        }));
+    }
+
+    decoders.push(Box::new(RegexDecoder {
+        name: "tara".to_string(),
+        re: Regex::new(&format!("{}(?:.*_{})?", TARA_PATTERN, PROTOCOL_CODE)).unwrap(),
+    }));
+
+    Ok(decoders)
+}
+
+// --------------------------------------------------
+/// Fills what the attribute parsers left empty from the sample's labels,
+/// noting each value and its source as inferred
+pub fn apply_decoders(sample: &mut Sample, decoders: &[Box<dyn LabelDecoder>]) {
+    let mut labels: Vec<(String, String)> = vec![];
+    if let Some(title) = &sample.title {
+        labels.push(("TITLE".to_string(), title.to_string()));
+    }
+    for attr in sample.attributes.iter().filter(|a| a.tag == EVENT_LABEL) {
+        labels.push((attr.tag.to_string(), attr.value.to_string()));
+    }
+
+    for (source, label) in labels {
+        for decoder in decoders {
+            let decoded = match decoder.decode(&label) {
+                Some(decoded) => decoded,
+                _ => continue,
+            };
+            let source = format!("{} ({})", source, decoder.name());
+
+            if let Some(mut date) = decoded.date {
+                if sample.collection_date().is_none() {
+                    date.tag = source.to_string();
+                    infer(sample, "collection_date", &date.value.to_rfc3339(), &source);
+                    sample.dates.push(date);
+                }
+            }
+
+            if let Some(depth) = decoded.depth {
+                if sample.depth.is_none() {
+                    sample.depth = Some(depth);
+                    if sample.depth_range.is_none() {
+                        sample.depth_range = Some((depth, depth));
+                    }
+                    infer(sample, "depth", &depth.to_string(), &source);
+                }
+            }
+
+            if let Some(station) = decoded.station {
+                infer(sample, "station", &station, &source);
+            }
+
+            if let Some(size) = decoded.size_fraction {
+                infer(sample, "size_fraction", &size, &source);
+            }
+        }
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+/// Records the first value found for a field
+fn infer(sample: &mut Sample, field: &str, value: &str, source: &str) {
+    if !sample.inferred.iter().any(|i| i.field == field) {
+        sample.inferred.push(Inferred {
+            field: field.to_string(),
+            value: value.to_string(),
+            source: source.to_string(),
+        });
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_tara_decoder() {
+    let decoders = get_decoders(&[]).unwrap();
+    let decoded = decoders[0]
+        .decode("TARA_20120309T0859Z_151_EVENT_PUMP_P_S_(5 m)_PROT_NUC-RNA(100L)_W0.8-5_TARA_N000002741")
+        .unwrap();
+    let date = decoded.date.unwrap();
+    assert_eq!(date.value.to_rfc3339(), "2012-03-09T08:59:00+00:00");
+    assert_eq!(date.precision, "minute");
+    assert_eq!(decoded.depth, Some(5.));
+    assert_eq!(decoded.station, Some("151".to_string()));
+    assert_eq!(decoded.size_fraction, Some("0.8-5".to_string()));
+
+    let decoded = decoders[0]
+        .decode("TARA_20120309T0859Z_151_EVENT_PUMP_W<5_TARA_N000002741")
+        .unwrap();
+    assert_eq!(decoded.size_fraction, Some("<5".to_string()));
+
+    let decoded = decoders[0]
+        .decode("TARA_20120309T0859Z_151_EVENT_PUMP")
+        .unwrap();
+    assert_eq!(decoded.depth, None);
+    assert_eq!(decoded.station, Some("151".to_string()));
+    assert!(decoders[0].decode("SIO_pier_0618").is_none());
+}
+
+// --------------------------------------------------
+#[test]
+fn test_apply_decoders() {
+    let decoders =
+        get_decoders(&[r"sio=^SIO_pier_(?P<month>\d{2})(?P<day>\d{2})$".to_string()]).unwrap();
+    assert!(get_decoders(&["=foo".to_string()]).is_err());
+    assert!(get_decoders(&["bad=(".to_string()]).is_err());
+
+    // a date from the regex decoder, without a year, is not a date
+    let mut sample = Sample {
+        title: Some("SIO_pier_0618".to_string()),
+        ..Default::default()
+    };
+    apply_decoders(&mut sample, &decoders);
+    assert!(sample.inferred.is_empty());
+
+    // attributes win over labels
+    let root =
+        ::xmltree::Element::parse(::std::fs::File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let mut sample = super::parse_xml(root, &[]).unwrap();
+    sample.depth = None;
+    apply_decoders(&mut sample, &decoders);
+    assert_eq!(
+        sample.collection_date().unwrap().tag,
+        "Event Date/Time Start"
+    );
+    assert_eq!(sample.depth, Some(5.));
+
+    let fields: Vec<(&str, &str, &str)> = sample
+        .inferred
+        .iter()
+        .map(|i| (i.field.as_str(), i.value.as_str(), i.source.as_str()))
+        .collect();
+    assert_eq!(
+        fields,
+        vec![
+            ("depth", "5", "TITLE (tara)"),
+            ("station", "151", "TITLE (tara)"),
+            ("size_fraction", "0.8-5", "TITLE (tara)"),
+        ]
+    );
+}
+
+// --------------------------------------------------
+#[test]
+fn test_decoders_in_parse_fields() {
+    let decoders = get_decoders(&[r"lab=^LAB_(?P<size>\d+-\d+)um$".to_string()]).unwrap();
+    let mut sample = Sample {
+        title: Some("LAB_3-20um".to_string()),
+        ..Default::default()
+    };
+    super::parse_fields(&mut sample, &decoders);
+    let size = sample.size_fraction.unwrap();
+    assert_eq!(
+        (size.label(), size.source.as_str()),
+        ("3-20".to_string(), "TITLE (lab)")
+    );
+
+    // a decoded depth does not replace a range from the attributes
+    let mut sample = Sample {
+        title: Some("TARA_20120309T0859Z_151_EVENT_PUMP_P_S_(5 m)".to_string()),
+        attributes: vec![super::Attr {
+            tag: "depth".to_string(),
+            value: "5-10 m".to_string(),
+            position: 1,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    super::parse_fields(&mut sample, &decoders);
+    assert_eq!(sample.depth, Some(5.));
+    assert_eq!(sample.depth_range, Some((5., 10.)));
+}
@@ -0,0 +1,295 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+use super::{
+    parse_datetime, parse_depth_range, signed_coord, Inferred, PossibleDate, Sample, Warning,
+};
+
+/// Where values mined from the text are said to come from
+const SOURCE: &str = "DESCRIPTION";
+
+/// Coordinates further apart than this (degrees) disagree
+const COORD_TOLERANCE: f64 = 0.01;
+
+/// Date precisions from finest to coarsest, with how to print a date to
+/// that precision
+pub const PRECISIONS: &[(&str, &str)] = &[
+    ("second", "%Y-%m-%dT%H:%M:%S"),
+    ("minute", "%Y-%m-%dT%H:%M"),
+    ("hour", "%Y-%m-%dT%H"),
+    ("day", "%Y-%m-%d"),
+    ("month", "%Y-%m"),
+];
+
+/// What the free text of a DESCRIPTION says
+#[derive(Debug, Default, PartialEq)]
+pub struct Mined {
+    pub lat_lon: Option<(f64, f64)>,
+    pub date: Option<(DateTime<Utc>, &'static str)>,
+    pub depth_range: Option<(f64, f64)>,
+}
+
+// --------------------------------------------------
+/// Finds coordinates, an ISO date/time and a depth phrase in free text
+pub fn mine_text(text: &str) -> Mined {
+    // latitudeN=36.1715, longitudeE=-29.023; lat: 36.17 S, long: 29.02 W;
+    // directions are upper case only, lest "latitudes" read as South
+    let named_re = Regex::new(
+        r"(?xi)
+        \blat(?:itude)?(?P<lat_dir>(?-i:[NS]))?
+        \s*[=:]\s*
+        (?P<lat>[-+]?\d+(?:\.\d+)?)\s*(?P<lat_dir2>(?-i:[NS])\b)?
+        [\s,;]*(?:and\s+)?
+        \blong?(?:itude)?(?P<lon_dir>(?-i:[EW]))?
+        \s*[=:]\s*
+        (?P<lon>[-+]?\d+(?:\.\d+)?)\s*(?P<lon_dir2>(?-i:[EW])\b)?
+        ",
+    )
+    .unwrap();
+
+    // 36.1715 N, 29.023 W
+    let pair_re = Regex::new(
+        r"(?x)
+        \b(?P<lat>\d{1,2}(?:\.\d+)?)\s*°?\s*(?P<lat_dir>[NS])
+        [\s,;]+
+        (?P<lon>\d{1,3}(?:\.\d+)?)\s*°?\s*(?P<lon_dir>[EW])\b
+        ",
+    )
+    .unwrap();
+
+    let date_re = Regex::new(r"\b\d{4}-\d{2}-\d{2}(?:T\d{1,2}:\d{2}(?::\d{2})?)?\b").unwrap();
+
+    // "at a depth of 3-7 m", "depth: 500 m"
+    let depth_re = Regex::new(
+        r"(?xi)
+        \bdepths?(?:\s+of|\s*[=:])
+        \s*(?:about\s+|approximately\s+|~\s*)?
+        (?P<depth>\d*\.?\d+(?:\s*(?:-|to)\s*\d*\.?\d+)?\s*(?:cm|mm|m|meters?)\b)
+        ",
+    )
+    .unwrap();
+
+    // The sign of a coordinate flips only for a S/W direction
+    let coord = |caps: &::regex::Captures, num: &str, dirs: &[&str]| {
+        let dir = dirs.iter().find_map(|d| caps.name(d)).map(|m| m.as_str());
+        match dir {
+            Some(d) if d.eq_ignore_ascii_case("S") || d.eq_ignore_ascii_case("W") => {
+                signed_coord(&caps[num], Some(d))
+            }
+            _ => caps[num].parse::<f64>().ok(),
+        }
+    };
+
+    let lat_lon = named_re
+        .captures(text)
+        .and_then(|caps| {
+            let lat = coord(&caps, "lat", &["lat_dir", "lat_dir2"])?;
+            let lon = coord(&caps, "lon", &["lon_dir", "lon_dir2"])?;
+            Some((lat, lon))
+        })
+        .or_else(|| {
+            pair_re.captures(text).and_then(|caps| {
+                let lat = signed_coord(&caps["lat"], Some(&caps["lat_dir"]))?;
+                let lon = signed_coord(&caps["lon"], Some(&caps["lon_dir"]))?;
+                Some((lat, lon))
+            })
+        })
+        .filter(|(lat, lon)| lat.abs() <= 90. && lon.abs() <= 180.);
+
+    Mined {
+        lat_lon,
+        date: date_re
+            .find_iter(text)
+            .find_map(|m| parse_datetime(m.as_str())),
+        depth_range: depth_re
+            .captures(text)
+            .and_then(|caps| parse_depth_range(&caps["depth"])),
+    }
+}
+
+// --------------------------------------------------
+/// Fills the sample's missing coordinates, date and depth from its
+/// DESCRIPTION and warns where the text disagrees with the attributes
+pub fn mine_description(sample: &mut Sample) {
+    let mined = match &sample.description {
+        Some(description) => mine_text(description),
+        _ => return,
+    };
+
+    if let Some((lat, lon)) = mined.lat_lon {
+        match sample.lat_lon {
+            None => {
+                sample.lat_lon = Some((lat, lon));
+                infer(sample, "lat_lon", &format!("{} {}", lat, lon));
+            }
+            Some((have_lat, have_lon)) => {
+                if (have_lat - lat).abs() > COORD_TOLERANCE
+                    || (have_lon - lon).abs() > COORD_TOLERANCE
+                {
+                    disagree(sample, "lat_lon", &format!("{} {}", lat, lon));
+                }
+            }
+        }
+    }
+
+    if let Some((value, precision)) = mined.date {
+        match sample.collection_date().map(|d| (d.value, d.precision)) {
+            None => {
+                infer(sample, "collection_date", &value.to_rfc3339());
+                sample.dates.push(PossibleDate {
+                    tag: SOURCE.to_string(),
+                    value,
+                    precision,
+                    tag_ok: true,
+                });
+            }
+            Some((have, have_precision)) => {
+                if !same_date(have, value, &[have_precision, precision]) {
+                    disagree(sample, "collection_date", &value.to_rfc3339());
+                }
+            }
+        }
+    }
+
+    // an attribute depth, or range, must lie within the text's range
+    if let Some((min, max)) = mined.depth_range {
+        match sample.depth.map(|d| (d, d)).or(sample.depth_range) {
+            None => {
+                if min == max {
+                    sample.depth = Some(min);
+                }
+                sample.depth_range = Some((min, max));
+                infer(sample, "depth", &format!("{}-{}", min, max));
+            }
+            Some((have_min, have_max)) => {
+                if have_min < min || have_max > max {
+                    disagree(sample, "depth", &format!("{}-{} m", min, max));
+                }
+            }
+        }
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn infer(sample: &mut Sample, field: &str, value: &str) {
+    sample.inferred.push(Inferred {
+        field: field.to_string(),
+        value: value.to_string(),
+        source: SOURCE.to_string(),
+    });
+}
+
+// --------------------------------------------------
+/// Whether the dates agree to the coarser of their precisions, but no
+/// finer than a day, so "2012-03" matches any day in March
+fn same_date(a: DateTime<Utc>, b: DateTime<Utc>, precisions: &[&str]) -> bool {
+    let rank = |precision: &str| PRECISIONS.iter().position(|(p, _)| *p == precision);
+    let coarsest = precisions
+        .iter()
+        .filter_map(|p| rank(p))
+        .chain(rank("day"))
+        .max()
+        .unwrap_or(0);
+    let format = PRECISIONS[coarsest].1;
+    a.format(format).to_string() == b.format(format).to_string()
+}
+
+// --------------------------------------------------
+fn disagree(sample: &mut Sample, field: &str, value: &str) {
+    sample.warnings.push(Warning {
+        field: field.to_string(),
+        message: format!(
+            "{} says {}, which disagrees with the attributes",
+            SOURCE, value
+        ),
+        position: None,
+    });
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_mine_text() {
+    let text = "collected at station TARA_151 (latitudeN=36.1715, longitudeE=-29.023) \
+                on date/time=2012-03-09T08:59, using a PUMP. The sample material was \
+                collected at a depth of 3-7 m, targeting a surface water layer";
+    let mined = mine_text(text);
+    assert_eq!(mined.lat_lon, Some((36.1715, -29.023)));
+    assert_eq!(
+        mined.date.map(|(d, p)| (d.to_rfc3339(), p)),
+        Some(("2012-03-09T08:59:00+00:00".to_string(), "minute"))
+    );
+    assert_eq!(mined.depth_range, Some((3., 7.)));
+
+    let mined = mine_text("Sediment from 41.5 N, 70.67 W at depth: 20 cm.");
+    assert_eq!(mined.lat_lon, Some((41.5, -70.67)));
+    assert_eq!(mined.depth_range, Some((0.2, 0.2)));
+    assert_eq!(mined.date, None);
+
+    assert_eq!(mine_text("Seawater"), Mined::default());
+
+    // only an upper-case letter is a direction
+    let mined = mine_text("latitudeS=36.5, longitudeW=29.5");
+    assert_eq!(mined.lat_lon, Some((-36.5, -29.5)));
+    assert_eq!(mine_text("latitudes: 12.5, longitudes: 20").lat_lon, None);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_mine_description() {
+    let sample = super::tara_sample();
+    assert!(sample.description.is_some());
+    assert!(!sample
+        .warnings
+        .iter()
+        .any(|w| w.message.starts_with(SOURCE)));
+    assert!(sample.inferred.is_empty());
+
+    let mut sample = Sample {
+        description: sample.description.clone(),
+        depth: Some(100.),
+        ..Default::default()
+    };
+    mine_description(&mut sample);
+    assert_eq!(sample.lat_lon, Some((36.1715, -29.023)));
+    assert_eq!(sample.collection_date().unwrap().tag, SOURCE);
+    assert_eq!(sample.inferred.len(), 2);
+    assert_eq!(sample.warnings.len(), 1);
+    assert_eq!(sample.warnings[0].field, "depth");
+
+    // nor does a depth range from the attributes give way silently
+    let depth = |range: (f64, f64)| {
+        let mut sample = Sample {
+            description: Some("collected at a depth of 3-7 m".to_string()),
+            depth_range: Some(range),
+            ..Default::default()
+        };
+        mine_description(&mut sample);
+        (sample.depth, sample.depth_range, sample.warnings.len())
+    };
+    assert_eq!(depth((4., 6.)), (None, Some((4., 6.)), 0));
+    assert_eq!(depth((5., 10.)), (None, Some((5., 10.)), 1));
+
+    // a month-precision date agrees with any day in that month
+    let dates = |attr_date: &str| {
+        let mut sample = Sample {
+            description: Some("Collected on 2012-03-09T08:59".to_string()),
+            attributes: vec![super::make_attr("collection date", attr_date, 1)],
+            ..Default::default()
+        };
+        sample.dates = super::get_dates(&sample.attributes).unwrap_or_default();
+        mine_description(&mut sample);
+        sample
+            .warnings
+            .iter()
+            .filter(|w| w.field == "collection_date")
+            .count()
+    };
+    assert_eq!(dates("2012-03"), 0);
+    assert_eq!(dates("2012-03-09"), 0);
+    assert_eq!(dates("2012-03-10"), 1);
+    assert_eq!(dates("2012-04"), 1);
+}
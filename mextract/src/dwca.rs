@@ -0,0 +1,247 @@
+use chrono::Utc;
+use csv::{QuoteStyle, Writer, WriterBuilder};
+use regex::Regex;
+use std::fs::File;
+use std::io::Write;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use super::export::SampleWriter;
+use super::provenance::Provenance;
+use super::{is_harmonized, Attr, MyResult, Sample};
+
+/// Darwin Core terms of the event core, in column order
+const EVENT_TERMS: &[&str] = &[
+    "eventID",
+    "eventDate",
+    "decimalLatitude",
+    "decimalLongitude",
+    "geodeticDatum",
+    "minimumDepthInMeters",
+    "maximumDepthInMeters",
+    "sampleSizeValue",
+    "sampleSizeUnit",
+    "habitat",
+];
+
+/// Writes a Darwin Core Archive with one event per sample
+pub struct DwcaWriter {
+    events: Option<Writer<ZipWriter<File>>>,
+    provenance: String,
+}
+
+impl DwcaWriter {
+    pub fn open(path: &str, prov: &Provenance) -> MyResult<DwcaWriter> {
+        let mut zip = ZipWriter::new(File::create(path)?);
+        zip.start_file("event.txt", FileOptions::default())?;
+
+        let mut events = WriterBuilder::new()
+            .delimiter(b'\t')
+            .quote_style(QuoteStyle::Never)
+            .from_writer(zip);
+        events.write_record(EVENT_TERMS)?;
+
+        Ok(DwcaWriter {
+            events: Some(events),
+            provenance: ::serde_json::to_string_pretty(&prov.to_json())?,
+        })
+    }
+}
+
+impl SampleWriter for DwcaWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        if let Some(events) = self.events.as_mut() {
+            events.write_record(event_row(sample).iter().map(|v| clean(v)))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        if let Some(events) = self.events.take() {
+            let mut zip = events.into_inner().map_err(|e| e.to_string())?;
+            zip.start_file("meta.xml", FileOptions::default())?;
+            zip.write_all(meta_xml().as_bytes())?;
+            zip.start_file("eml.xml", FileOptions::default())?;
+            zip.write_all(eml_xml().as_bytes())?;
+            zip.start_file("provenance.json", FileOptions::default())?;
+            zip.write_all(self.provenance.as_bytes())?;
+            zip.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn event_row(sample: &Sample) -> Vec<String> {
+    let opt = |val: Option<f64>| val.map(|v| v.to_string()).unwrap_or_default();
+    let (size, size_unit) = get_sample_size(&sample.attributes).unwrap_or_default();
+
+    vec![
+        sample.accession.to_string(),
+        sample
+            .collection_date()
+            .map(|d| {
+                let fmt = match d.precision {
+                    "month" => "%Y-%m",
+                    "day" => "%Y-%m-%d",
+                    _ => "%Y-%m-%dT%H:%M:%SZ",
+                };
+                d.value.format(fmt).to_string()
+            })
+            .unwrap_or_default(),
+        opt(sample.lat_lon.map(|(lat, _)| lat)),
+        opt(sample.lat_lon.map(|(_, lon)| lon)),
+        if sample.lat_lon.is_some() {
+            "WGS84"
+        } else {
+            ""
+        }
+        .to_string(),
+        opt(sample.depth_range.map(|(min, _)| min)),
+        opt(sample.depth_range.map(|(_, max)| max)),
+        size,
+        size_unit,
+        get_habitat(&sample.attributes),
+    ]
+}
+
+// --------------------------------------------------
+/// Values can't be quoted with fieldsEnclosedBy=""
+fn clean(val: &str) -> String {
+    val.replace(['\t', '\r', '\n'], " ")
+}
+
+// --------------------------------------------------
+/// The ENVO-annotated attributes (biome, feature, material), joined
+fn get_habitat(attrs: &[Attr]) -> String {
+    attrs
+        .iter()
+        .filter(|a| a.value.contains("ENVO:"))
+        .map(|a| a.value.trim_matches('"').to_string())
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+// --------------------------------------------------
+/// Value and unit of MIxS samp_size or a "sample volume"-like attribute
+fn get_sample_size(attrs: &[Attr]) -> Option<(String, String)> {
+    let tag_re = Regex::new(r"(?i)^samp(?:le)?[\s_](?:size|vol(?:ume)?)$").unwrap();
+    let value_re = Regex::new(r"^\s*(?P<num>\d*\.?\d+)\s*(?P<unit>.*?)\s*$").unwrap();
+
+    let attr = attrs
+        .iter()
+        .find(|a| is_harmonized(a, "samp_size"))
+        .or_else(|| attrs.iter().find(|a| tag_re.is_match(&a.tag)))?;
+
+    value_re.captures(&attr.value).map(|caps| {
+        let unit = match &caps["unit"] {
+            "" => attr.units.clone().unwrap_or_default(),
+            unit => unit.to_string(),
+        };
+        (caps["num"].to_string(), unit)
+    })
+}
+
+// --------------------------------------------------
+fn meta_xml() -> String {
+    let fields: Vec<String> = EVENT_TERMS
+        .iter()
+        .enumerate()
+        .map(|(i, term)| {
+            format!(
+                r#"    <field index="{}" term="http://rs.tdwg.org/dwc/terms/{}"/>"#,
+                i, term
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<archive xmlns="http://rs.tdwg.org/dwc/text/" metadata="eml.xml">
+  <core encoding="UTF-8" fieldsTerminatedBy="\t" linesTerminatedBy="\n" fieldsEnclosedBy="" ignoreHeaderLines="1" rowType="http://rs.tdwg.org/dwc/terms/Event">
+    <files>
+      <location>event.txt</location>
+    </files>
+    <id index="0"/>
+{}
+  </core>
+</archive>
+"#,
+        fields.join("\n")
+    )
+}
+
+// --------------------------------------------------
+/// A minimal EML document for the publisher to fill in
+fn eml_xml() -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<eml:eml xmlns:eml="eml://ecoinformatics.org/eml-2.1.1" packageId="mextract" system="mextract" scope="system">
+  <dataset>
+    <title>ENA sample metadata</title>
+    <creator>
+      <!-- fill in before publishing -->
+      <organizationName>Unknown</organizationName>
+    </creator>
+    <pubDate>{}</pubDate>
+    <abstract>
+      <para>Sample events extracted from ENA sample metadata by mextract.</para>
+    </abstract>
+  </dataset>
+</eml:eml>
+"#,
+        Utc::now().format("%Y-%m-%d")
+    )
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_event_row() {
+    let root = ::xmltree::Element::parse(File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let mut sample = super::parse_xml(root, &[]).unwrap();
+    sample.attributes.push(Attr {
+        tag: "samp_size".to_string(),
+        value: "100 L".to_string(),
+        ..Default::default()
+    });
+
+    let row = event_row(&sample);
+    assert_eq!(row[0], "ERS494529");
+    assert_eq!(row[1], "2012-03-09T08:59:00Z");
+    assert_eq!(row[2], "36.1715");
+    assert_eq!(row[5], "5");
+    assert_eq!(row[6], "5");
+    assert_eq!(row[7], "100");
+    assert_eq!(row[8], "L");
+    assert!(row[9].starts_with("marine biome (ENVO:00000447) | "));
+    assert_eq!(row.len(), EVENT_TERMS.len());
+}
+
+// --------------------------------------------------
+#[test]
+fn test_dwca_archive() {
+    let path = ::std::env::temp_dir().join(format!("mextract-{}.zip", ::std::process::id()));
+    let path = path.to_str().unwrap();
+    let mut writer =
+        DwcaWriter::open(path, &super::provenance::get_provenance(&[]).unwrap()).unwrap();
+    writer
+        .write(&Sample {
+            accession: "ERS1".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+    writer.finish().unwrap();
+
+    let archive = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+    let mut names: Vec<&str> = archive.file_names().collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["eml.xml", "event.txt", "meta.xml", "provenance.json"]
+    );
+    ::std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,196 @@
+use chrono::NaiveDate;
+
+use super::{MyResult, Sample, Warning};
+
+/// Typed values of ENA's own ENA-* attributes (or their SRA/BioSample
+/// equivalents)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnaInfo {
+    pub checklist: Option<String>,
+    pub spot_count: Option<u64>,
+    pub base_count: Option<u64>,
+    pub first_public: Option<NaiveDate>,
+    pub last_update: Option<NaiveDate>,
+}
+
+/// Sequencing depth and publication date constraints; samples without
+/// the value in question are dropped
+#[derive(Debug, Default)]
+pub struct EnaFilter {
+    pub min_spots: Option<u64>,
+    pub min_bases: Option<u64>,
+    pub public_after: Option<NaiveDate>,
+    pub public_before: Option<NaiveDate>,
+}
+
+impl EnaFilter {
+    pub fn is_empty(&self) -> bool {
+        self.min_spots.is_none()
+            && self.min_bases.is_none()
+            && self.public_after.is_none()
+            && self.public_before.is_none()
+    }
+
+    pub fn accepts(&self, sample: &Sample) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let ena = &sample.ena;
+        let at_least = |min: Option<u64>, have: Option<u64>| match min {
+            Some(min) => have.is_some_and(|have| have >= min),
+            _ => true,
+        };
+        let public = |date: Option<NaiveDate>, ok: &dyn Fn(NaiveDate, NaiveDate) -> bool| match date
+        {
+            Some(date) => ena.first_public.is_some_and(|have| ok(have, date)),
+            _ => true,
+        };
+
+        at_least(self.min_spots, ena.spot_count)
+            && at_least(self.min_bases, ena.base_count)
+            && public(self.public_after, &|have, date| have >= date)
+            && public(self.public_before, &|have, date| have <= date)
+    }
+}
+
+// --------------------------------------------------
+/// Sets the typed fields from the sample's ENA-* attributes, keeping any
+/// already set from elsewhere, and warns about values that do not parse
+pub fn parse_ena(sample: &mut Sample) {
+    let mut warnings = vec![];
+    for attr in &sample.ena_attributes {
+        let value = attr.value.trim();
+        let ok = match attr.tag.as_str() {
+            "ENA-CHECKLIST" => {
+                sample.ena.checklist = Some(value.to_string());
+                true
+            }
+            "ENA-SPOT-COUNT" => set(&mut sample.ena.spot_count, value.parse().ok()),
+            "ENA-BASE-COUNT" => set(&mut sample.ena.base_count, value.parse().ok()),
+            "ENA-FIRST-PUBLIC" => set(&mut sample.ena.first_public, parse_date(value)),
+            "ENA-LAST-UPDATE" => set(&mut sample.ena.last_update, parse_date(value)),
+            _ => true,
+        };
+
+        if !ok {
+            warnings.push(Warning {
+                field: attr.tag.to_lowercase(),
+                message: format!("could not parse \"{}\" = \"{}\"", attr.tag, attr.value),
+                position: Some(attr.position),
+            });
+        }
+    }
+    sample.warnings.extend(warnings);
+}
+
+// --------------------------------------------------
+/// Takes the date from "2016-03-01" or "2016-03-01T00:00:00.000"
+pub fn parse_date(val: &str) -> Option<NaiveDate> {
+    val.get(..10)
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+}
+
+// --------------------------------------------------
+/// Parses a --public-after/--public-before argument
+pub fn parse_date_arg(val: Option<&str>) -> MyResult<Option<NaiveDate>> {
+    match val {
+        Some(val) => match parse_date(val).filter(|_| val.len() == 10) {
+            Some(date) => Ok(Some(date)),
+            _ => Err(From::from(format!("\"{}\" is not a YYYY-MM-DD date", val))),
+        },
+        _ => Ok(None),
+    }
+}
+
+// --------------------------------------------------
+/// Parses a --min-spots/--min-bases argument
+pub fn parse_count_arg(val: Option<&str>) -> MyResult<Option<u64>> {
+    match val {
+        Some(val) => match val.parse::<u64>() {
+            Ok(n) => Ok(Some(n)),
+            _ => Err(From::from(format!("\"{}\" is not a count", val))),
+        },
+        _ => Ok(None),
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn set<T>(field: &mut Option<T>, val: Option<T>) -> bool {
+    let ok = val.is_some();
+    if ok {
+        *field = val;
+    }
+    ok
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_parse_ena() {
+    let sample = super::tara_sample();
+    assert_eq!(
+        sample.ena,
+        EnaInfo {
+            checklist: Some("ERC000030".to_string()),
+            spot_count: Some(344079827),
+            base_count: Some(67515585092),
+            first_public: NaiveDate::from_ymd_opt(2015, 5, 5),
+            last_update: NaiveDate::from_ymd_opt(2015, 11, 4),
+        }
+    );
+
+    let mut sample = Sample {
+        ena_attributes: vec![super::make_attr("ENA-SPOT-COUNT", "many", 7)],
+        ..Default::default()
+    };
+    parse_ena(&mut sample);
+    assert_eq!(sample.ena.spot_count, None);
+    assert_eq!(sample.warnings[0].field, "ena-spot-count");
+    assert_eq!(sample.warnings[0].position, Some(7));
+}
+
+// --------------------------------------------------
+#[test]
+fn test_ena_filter() {
+    let sample = Sample {
+        ena: EnaInfo {
+            spot_count: Some(1000),
+            first_public: NaiveDate::from_ymd_opt(2015, 5, 5),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    assert!(EnaFilter::default().accepts(&sample));
+
+    let filter = EnaFilter {
+        min_spots: Some(1000),
+        public_after: parse_date_arg(Some("2015-01-01")).unwrap(),
+        public_before: parse_date_arg(Some("2015-05-05")).unwrap(),
+        ..Default::default()
+    };
+    assert!(filter.accepts(&sample));
+
+    let filter = EnaFilter {
+        min_spots: Some(1001),
+        ..Default::default()
+    };
+    assert!(!filter.accepts(&sample));
+
+    // no base count at all
+    let filter = EnaFilter {
+        min_bases: Some(1),
+        ..Default::default()
+    };
+    assert!(!filter.accepts(&sample));
+
+    assert!(parse_date_arg(Some("2015-05")).is_err());
+    assert!(parse_date_arg(Some("2015-05-05T00:00")).is_err());
+    assert_eq!(parse_date_arg(None).unwrap(), None);
+    assert_eq!(parse_count_arg(Some("1000")).unwrap(), Some(1000));
+    assert!(parse_count_arg(Some("1e6")).is_err());
+}
@@ -0,0 +1,445 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use xmltree::Element;
+
+use super::{Attr, MyResult, Sample, Warning};
+
+/// MIxS environment fields and the other tags they are submitted under
+const ENV_FIELDS: &[(&str, &[&str])] = &[
+    (
+        "env_broad_scale",
+        &[
+            "env_broad_scale",
+            "broad-scale environmental context",
+            "environment (biome)",
+            "env_biome",
+            "biome",
+        ],
+    ),
+    (
+        "env_local_scale",
+        &[
+            "env_local_scale",
+            "local environmental context",
+            "environment (feature)",
+            "env_feature",
+            "feature",
+        ],
+    ),
+    (
+        "env_medium",
+        &[
+            "env_medium",
+            "environmental medium",
+            "environment (material)",
+            "env_material",
+            "material",
+        ],
+    ),
+];
+
+/// A label and/or ontology ID from one of the environment fields
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvTerm {
+    pub field: &'static str,
+    pub label: Option<String>,
+    /// Normalized to "ENVO:00000447"; None for placeholders like ENVO:xxxxxxxx
+    pub id: Option<String>,
+    pub position: usize,
+}
+
+/// A class from an ENVO OBO or OWL file
+#[derive(Debug, Default, PartialEq)]
+pub struct OntologyTerm {
+    pub name: String,
+    pub synonyms: Vec<String>,
+    pub obsolete: bool,
+    pub replaced_by: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Ontology {
+    terms: HashMap<String, OntologyTerm>,
+    /// Lowercased names and exact synonyms to the IDs that carry them
+    labels: HashMap<String, Vec<String>>,
+}
+
+impl Ontology {
+    /// Reports environment IDs that are unknown or obsolete and labels
+    /// that are neither the term's name nor one of its exact synonyms;
+    /// a label without an ID is looked up by name and exact synonym
+    pub fn validate(&self, sample: &mut Sample) {
+        let mut warnings = vec![];
+        for env in &sample.env_terms {
+            let warn = |message: String| Warning {
+                field: env.field.to_string(),
+                message,
+                position: Some(env.position),
+            };
+
+            let id = match (&env.id, &env.label) {
+                (Some(id), _) => id,
+                (_, Some(label)) => match self.find_label(label) {
+                    Some(id) => id,
+                    _ => {
+                        warnings.push(warn(format!("label \"{}\" is not in the ontology", label)));
+                        continue;
+                    }
+                },
+                _ => continue,
+            };
+
+            let term = match self.terms.get(id) {
+                Some(term) => term,
+                _ => {
+                    warnings.push(warn(format!("{} is not in the ontology", id)));
+                    continue;
+                }
+            };
+
+            if term.obsolete {
+                warnings.push(warn(match &term.replaced_by {
+                    Some(new) => format!("{} is obsolete, replaced by {}", id, new),
+                    _ => format!("{} is obsolete", id),
+                }));
+            }
+
+            if let Some(label) = &env.label {
+                let matches = |name: &String| name.eq_ignore_ascii_case(label);
+                if !matches(&term.name) && !term.synonyms.iter().any(matches) {
+                    warnings.push(warn(format!(
+                        "label \"{}\" does not match {} \"{}\"",
+                        label, id, term.name
+                    )));
+                }
+            }
+        }
+        sample.warnings.extend(warnings);
+    }
+
+    /// The ID whose name or exact synonym is the label, preferring a
+    /// current term over an obsolete one
+    fn find_label(&self, label: &str) -> Option<&String> {
+        let ids = self.labels.get(&label.to_lowercase())?;
+        ids.iter()
+            .find(|id| !self.terms[*id].obsolete)
+            .or_else(|| ids.first())
+    }
+}
+
+// --------------------------------------------------
+/// Parses the environment attributes into labels and IDs; an attribute
+/// may hold several "label (ENVO:...)" terms separated by "|" or ";"
+pub fn get_env_terms(attrs: &[Attr]) -> Vec<EnvTerm> {
+    let curie_re = Regex::new(r"(?i)\bENVO[:_](?P<num>[[:alnum:]]+)").unwrap();
+    let mut terms = vec![];
+    for attr in attrs {
+        let tag = attr.tag.to_lowercase();
+        let field = ENV_FIELDS.iter().find(|(field, tags)| {
+            attr.harmonized_name.as_deref() == Some(field) || tags.contains(&tag.as_str())
+        });
+
+        if let Some((field, _)) = field {
+            for part in attr.value.split(['|', ';']) {
+                let (label, id) = match curie_re.captures(part) {
+                    Some(caps) => {
+                        let num = &caps["num"];
+                        let id = if num.chars().all(|c| c.is_ascii_digit()) {
+                            Some(format!("ENVO:{:0>8}", num))
+                        } else {
+                            None
+                        };
+                        (&part[..caps.get(0).map_or(0, |m| m.start())], id)
+                    }
+                    _ => (part, None),
+                };
+
+                let label = clean_label(label);
+                if label.is_some() || id.is_some() || curie_re.is_match(part) {
+                    terms.push(EnvTerm {
+                        field,
+                        label,
+                        id,
+                        position: attr.position,
+                    });
+                }
+            }
+        }
+    }
+
+    terms
+}
+
+// --------------------------------------------------
+/// Warns about placeholder or malformed ENVO IDs, which need no ontology
+pub fn get_env_warnings(sample: &Sample) -> Vec<Warning> {
+    let attr_value = |position: usize| {
+        sample
+            .attributes
+            .iter()
+            .find(|a| a.position == position)
+            .map(|a| a.value.to_string())
+            .unwrap_or_default()
+    };
+
+    sample
+        .env_terms
+        .iter()
+        .filter(|t| t.id.is_none())
+        .filter(|t| attr_value(t.position).to_uppercase().contains("ENVO"))
+        .map(|t| Warning {
+            field: t.field.to_string(),
+            message: format!(
+                "placeholder or malformed ENVO ID in \"{}\"",
+                attr_value(t.position)
+            ),
+            position: Some(t.position),
+        })
+        .collect()
+}
+
+// --------------------------------------------------
+/// Reads ENVO as OBO or, when the file starts with "<", as RDF/XML OWL
+pub fn load(path: &str) -> MyResult<Ontology> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let is_xml = reader.fill_buf()?.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<');
+    let terms = if is_xml {
+        read_owl(reader)?
+    } else {
+        read_obo(reader)?
+    };
+
+    if terms.is_empty() {
+        return Err(From::from(format!("No ENVO terms in \"{}\"", path)));
+    }
+
+    let mut labels: HashMap<String, Vec<String>> = HashMap::new();
+    for (id, term) in &terms {
+        for label in ::std::iter::once(&term.name).chain(&term.synonyms) {
+            labels
+                .entry(label.to_lowercase())
+                .or_default()
+                .push(id.to_string());
+        }
+    }
+
+    Ok(Ontology { terms, labels })
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+/// Trims the brackets, quotes and punctuation around a label
+fn clean_label(label: &str) -> Option<String> {
+    let label = label
+        .trim()
+        .trim_end_matches(['(', '['])
+        .trim()
+        .trim_matches(['"', '\'', ',', ':'])
+        .trim();
+
+    if label.is_empty() {
+        None
+    } else {
+        Some(label.to_string())
+    }
+}
+
+// --------------------------------------------------
+fn read_obo(reader: impl BufRead) -> MyResult<HashMap<String, OntologyTerm>> {
+    let synonym_re = Regex::new(r#"^"(?P<name>.*)"\s+EXACT\b"#)?;
+    let mut terms = HashMap::new();
+    let mut id: Option<String> = None;
+    let mut term = OntologyTerm::default();
+    let mut in_term = false;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.starts_with('[') {
+            if let Some(id) = id.take() {
+                terms.insert(id, term);
+            }
+            term = OntologyTerm::default();
+            in_term = line == "[Term]";
+            continue;
+        }
+
+        if !in_term {
+            continue;
+        }
+
+        if let Some((key, val)) = line.split_once(": ") {
+            match key {
+                "id" => id = Some(val.to_string()),
+                "name" => term.name = val.to_string(),
+                "synonym" => {
+                    if let Some(caps) = synonym_re.captures(val) {
+                        term.synonyms.push(caps["name"].to_string());
+                    }
+                }
+                "is_obsolete" => term.obsolete = val == "true",
+                "replaced_by" => term.replaced_by = Some(val.to_string()),
+                _ => (),
+            }
+        }
+    }
+
+    if let Some(id) = id {
+        terms.insert(id, term);
+    }
+
+    Ok(terms)
+}
+
+// --------------------------------------------------
+fn read_owl(mut reader: impl Read) -> MyResult<HashMap<String, OntologyTerm>> {
+    let mut xml = vec![];
+    reader.read_to_end(&mut xml)?;
+    let root = Element::parse(xml.as_slice())?;
+
+    // "http://purl.obolibrary.org/obo/ENVO_00000447" => "ENVO:00000447"
+    let curie = |iri: &String| iri.rsplit('/').next().map(|id| id.replacen('_', ":", 1));
+
+    let mut terms = HashMap::new();
+    for class in root.children.iter().filter(|c| c.name == "Class") {
+        let id = match class.attributes.get("about").and_then(curie) {
+            Some(id) => id,
+            _ => continue,
+        };
+
+        let mut term = OntologyTerm::default();
+        for child in &class.children {
+            let text = || child.text.clone().unwrap_or_default();
+            match child.name.as_str() {
+                "label" => term.name = text(),
+                "hasExactSynonym" => term.synonyms.push(text()),
+                "deprecated" => term.obsolete = text() == "true",
+                "IAO_0100001" => {
+                    term.replaced_by = child.attributes.get("resource").and_then(curie)
+                }
+                _ => (),
+            }
+        }
+        terms.insert(id, term);
+    }
+
+    Ok(terms)
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_get_env_terms() {
+    let terms = get_env_terms(&[
+        super::make_attr("Environment (Biome)", "marine biome (ENVO:00000447)", 1),
+        super::make_attr(
+            "Environment (Material)",
+            "\"particulate matter, including plankton (ENVO:xxxxxxxx)\"",
+            2,
+        ),
+        super::make_attr("env_medium", "sea water [ENVO_2149]|surface layer", 3),
+        super::make_attr("temp", "19.5", 4),
+    ]);
+
+    assert_eq!(terms.len(), 4);
+    assert_eq!(terms[0].field, "env_broad_scale");
+    assert_eq!(terms[0].label, Some("marine biome".to_string()));
+    assert_eq!(terms[0].id, Some("ENVO:00000447".to_string()));
+    assert_eq!(terms[1].field, "env_medium");
+    assert_eq!(
+        terms[1].label,
+        Some("particulate matter, including plankton".to_string())
+    );
+    assert_eq!(terms[1].id, None);
+    assert_eq!(terms[2].id, Some("ENVO:00002149".to_string()));
+    assert_eq!(terms[3].label, Some("surface layer".to_string()));
+    assert_eq!(terms[3].id, None);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_load() {
+    let obo = load("test/envo.obo").unwrap();
+    assert_eq!(obo.terms.len(), 5);
+    assert_eq!(obo.terms["ENVO:00002042"].synonyms, vec!["surface layer"]);
+    assert!(obo.terms["ENVO:00000447"].synonyms.is_empty());
+
+    let owl = load("test/envo.owl").unwrap();
+    assert_eq!(owl.terms.len(), 3);
+    for id in &["ENVO:00000447", "ENVO:00002149", "ENVO:00002150"] {
+        assert_eq!(owl.terms[*id], obo.terms[*id]);
+    }
+}
+
+// --------------------------------------------------
+#[test]
+fn test_validate() {
+    let mut sample = super::tara_sample();
+    assert_eq!(sample.env_terms.len(), 3);
+    let placeholders: Vec<&Warning> = sample
+        .warnings
+        .iter()
+        .filter(|w| w.field == "env_medium")
+        .collect();
+    assert_eq!(placeholders.len(), 1);
+
+    // the placeholder's label is looked up on its own
+    let ontology = load("test/envo.obo").unwrap();
+    let count = sample.warnings.len();
+    ontology.validate(&mut sample);
+    assert_eq!(sample.warnings.len(), count + 1);
+    assert_eq!(
+        sample.warnings[count].message,
+        "label \"particulate matter, including plankton\" is not in the ontology"
+    );
+
+    sample.env_terms = vec![
+        EnvTerm {
+            field: "env_medium",
+            label: Some("seawater".to_string()),
+            id: Some("ENVO:00002150".to_string()),
+            position: 1,
+        },
+        EnvTerm {
+            field: "env_local_scale",
+            label: Some("sea surface".to_string()),
+            id: Some("ENVO:00002042".to_string()),
+            position: 2,
+        },
+        EnvTerm {
+            field: "env_broad_scale",
+            label: None,
+            id: Some("ENVO:99999999".to_string()),
+            position: 3,
+        },
+        EnvTerm {
+            field: "env_medium",
+            label: Some("Seawater".to_string()),
+            id: None,
+            position: 4,
+        },
+        EnvTerm {
+            field: "env_medium",
+            label: Some("sea slime".to_string()),
+            id: None,
+            position: 5,
+        },
+    ];
+    sample.warnings.clear();
+    ontology.validate(&mut sample);
+    let messages: Vec<&str> = sample.warnings.iter().map(|w| w.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "ENVO:00002150 is obsolete, replaced by ENVO:00002149",
+            "label \"seawater\" does not match ENVO:00002150 \"obsolete coastal sea water\"",
+            "label \"sea surface\" does not match ENVO:00002042 \"surface water layer\"",
+            "ENVO:99999999 is not in the ontology",
+            "label \"sea slime\" is not in the ontology",
+        ]
+    );
+}
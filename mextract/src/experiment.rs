@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use xmltree::Element;
+
+use super::{get_child_text, get_primary_id, read_sets, set_members, MyResult, Sample};
+
+/// Library and platform details from an EXPERIMENT
+#[derive(Debug, Clone, Default)]
+pub struct Experiment {
+    pub accession: String,
+    pub sample: Option<String>,
+    pub library_strategy: Option<String>,
+    pub library_source: Option<String>,
+    pub library_selection: Option<String>,
+    pub library_layout: Option<String>,
+    pub platform: Option<String>,
+    pub instrument_model: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub accession: String,
+    pub experiment: Option<String>,
+}
+
+/// A sample's run joined to the experiment that produced it
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub run: String,
+    pub experiment: Option<Experiment>,
+}
+
+/// Case-insensitive constraints on the experiment behind each run
+#[derive(Debug, Default)]
+pub struct RunFilter {
+    pub library_strategy: Option<String>,
+    pub library_source: Option<String>,
+    pub platform: Option<String>,
+}
+
+impl RunFilter {
+    pub fn is_empty(&self) -> bool {
+        self.library_strategy.is_none() && self.library_source.is_none() && self.platform.is_none()
+    }
+
+    fn accepts(&self, info: &RunInfo) -> bool {
+        let check = |want: &Option<String>, have: Option<&String>| match want {
+            Some(w) => have.is_some_and(|h| h.eq_ignore_ascii_case(w)),
+            _ => true,
+        };
+
+        match &info.experiment {
+            Some(exp) => {
+                check(&self.library_strategy, exp.library_strategy.as_ref())
+                    && check(&self.library_source, exp.library_source.as_ref())
+                    && check(&self.platform, exp.platform.as_ref())
+            }
+            _ => self.is_empty(),
+        }
+    }
+}
+
+// --------------------------------------------------
+pub fn read_experiments(paths: &[String]) -> MyResult<HashMap<String, Experiment>> {
+    let mut experiments = HashMap::new();
+    for root in read_sets(paths)? {
+        for elem in set_members(&root, "EXPERIMENT") {
+            let exp = parse_experiment(elem)?;
+            experiments.insert(exp.accession.to_string(), exp);
+        }
+    }
+
+    Ok(experiments)
+}
+
+// --------------------------------------------------
+pub fn read_runs(paths: &[String]) -> MyResult<HashMap<String, Run>> {
+    let mut runs = HashMap::new();
+    for root in read_sets(paths)? {
+        for elem in set_members(&root, "RUN") {
+            let run = parse_run(elem)?;
+            runs.insert(run.accession.to_string(), run);
+        }
+    }
+
+    Ok(runs)
+}
+
+// --------------------------------------------------
+/// Attaches experiment details to each of the sample's runs, following
+/// the run's EXPERIMENT_REF or, failing that, the sample's only
+/// ENA-EXPERIMENT link; details already on the sample (e.g., from an SRA
+/// run table) are kept when the XML has nothing for the run
+pub fn join_runs(
+    sample: &mut Sample,
+    experiments: &HashMap<String, Experiment>,
+    runs: &HashMap<String, Run>,
+) {
+    let linked: Vec<&Experiment> = sample
+        .experiments
+        .iter()
+        .filter_map(|acc| experiments.get(acc))
+        .collect();
+
+    let known = &sample.run_info;
+    sample.run_info = sample
+        .runs
+        .iter()
+        .map(|run| {
+            let experiment = runs
+                .get(run)
+                .and_then(|r| r.experiment.as_ref())
+                .and_then(|acc| experiments.get(acc))
+                .or_else(|| {
+                    if linked.len() == 1 {
+                        Some(linked[0])
+                    } else {
+                        None
+                    }
+                })
+                .or_else(|| {
+                    known
+                        .iter()
+                        .find(|i| &i.run == run)
+                        .and_then(|i| i.experiment.as_ref())
+                })
+                .cloned();
+
+            RunInfo {
+                run: run.to_string(),
+                experiment,
+            }
+        })
+        .collect();
+}
+
+// --------------------------------------------------
+/// Drops the runs the filter rejects; returns false when none are left
+pub fn filter_runs(sample: &mut Sample, filter: &RunFilter) -> bool {
+    if filter.is_empty() {
+        return true;
+    }
+
+    sample.run_info.retain(|info| filter.accepts(info));
+    let keep: Vec<String> = sample.run_info.iter().map(|i| i.run.to_string()).collect();
+    sample.runs.retain(|run| keep.contains(run));
+
+    !sample.runs.is_empty()
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn ref_accession(elem: &Element, name: &str) -> Option<String> {
+    elem.get_child(name).and_then(|r| {
+        r.attributes
+            .get("accession")
+            .cloned()
+            .or_else(|| get_primary_id(r).ok())
+    })
+}
+
+// --------------------------------------------------
+fn parse_experiment(elem: &Element) -> MyResult<Experiment> {
+    let mut exp = Experiment {
+        accession: accession(elem)?,
+        ..Default::default()
+    };
+
+    if let Some(design) = elem.get_child("DESIGN") {
+        exp.sample = ref_accession(design, "SAMPLE_DESCRIPTOR");
+        if let Some(lib) = design.get_child("LIBRARY_DESCRIPTOR") {
+            exp.library_strategy = get_child_text(lib, "LIBRARY_STRATEGY");
+            exp.library_source = get_child_text(lib, "LIBRARY_SOURCE");
+            exp.library_selection = get_child_text(lib, "LIBRARY_SELECTION");
+            exp.library_layout = lib
+                .get_child("LIBRARY_LAYOUT")
+                .and_then(|layout| layout.children.first())
+                .map(|layout| layout.name.to_string());
+        }
+    }
+
+    if let Some(platform) = elem.get_child("PLATFORM").and_then(|p| p.children.first()) {
+        exp.platform = Some(platform.name.to_string());
+        exp.instrument_model = get_child_text(platform, "INSTRUMENT_MODEL");
+    }
+
+    Ok(exp)
+}
+
+// --------------------------------------------------
+fn parse_run(elem: &Element) -> MyResult<Run> {
+    Ok(Run {
+        accession: accession(elem)?,
+        experiment: ref_accession(elem, "EXPERIMENT_REF"),
+    })
+}
+
+// --------------------------------------------------
+fn accession(elem: &Element) -> MyResult<String> {
+    match elem.attributes.get("accession") {
+        Some(acc) => Ok(acc.to_string()),
+        _ => get_primary_id(elem),
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_read_experiments() {
+    let exps = read_experiments(&["test/experiments.xml".to_string()]).unwrap();
+    assert_eq!(exps.len(), 4);
+
+    let exp = &exps["ERX1789536"];
+    assert_eq!(exp.sample, Some("ERS494529".to_string()));
+    assert_eq!(exp.library_strategy, Some("WGS".to_string()));
+    assert_eq!(exp.library_source, Some("METAGENOMIC".to_string()));
+    assert_eq!(exp.library_layout, Some("PAIRED".to_string()));
+    assert_eq!(exp.platform, Some("ILLUMINA".to_string()));
+    assert_eq!(
+        exp.instrument_model,
+        Some("Illumina HiSeq 2000".to_string())
+    );
+}
+
+// --------------------------------------------------
+#[test]
+fn test_join_and_filter_runs() {
+    let root = Element::parse(::std::fs::File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let mut sample = super::parse_xml(root, &[]).unwrap();
+    let exps = read_experiments(&["test/experiments.xml".to_string()]).unwrap();
+    let runs = read_runs(&["test/runs.xml".to_string()]).unwrap();
+    join_runs(&mut sample, &exps, &runs);
+    assert_eq!(sample.run_info.len(), 4);
+    assert!(sample.run_info.iter().all(|i| i.experiment.is_some()));
+
+    let filter = RunFilter {
+        library_strategy: Some("wgs".to_string()),
+        library_source: None,
+        platform: Some("illumina".to_string()),
+    };
+    assert!(filter_runs(&mut sample, &filter));
+    assert_eq!(sample.runs, vec!["ERR1719440".to_string()]);
+
+    let filter = RunFilter {
+        library_strategy: Some("AMPLICON".to_string()),
+        ..Default::default()
+    };
+    assert!(!filter_runs(&mut sample, &filter));
+}
@@ -0,0 +1,686 @@
+use clap::ArgMatches;
+use csv::{QuoteStyle, Writer, WriterBuilder};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::columnar::ParquetWriter;
+use super::dwca::DwcaWriter;
+use super::geojson::GeoJsonWriter;
+use super::partition::{PartitionBy, PartitionWriter};
+use super::pivot::WideWriter;
+use super::provenance::Provenance;
+use super::sqlite::SqliteWriter;
+use super::taxonomy::{self, RANKS};
+use super::xlsx::XlsxWriter;
+use super::{Attr, MyResult, Sample};
+
+/// The columns of the original xml2tab.pl output
+pub const DEFAULT_COLUMNS: &str = "sample,collection_date,latitude,longitude,depth,runs";
+
+/// Names accepted in --columns besides "attr:<TAG>"
+const FIELDS: &[&str] = &[
+    "accession",
+    "sample",
+    "biosample",
+    "title",
+    "description",
+    "taxon_id",
+    "scientific_name",
+    "common_name",
+    "lineage",
+    "lineage_ids",
+    "domain",
+    "superkingdom",
+    "kingdom",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "species",
+    "collection_date",
+    "collection_date_tag",
+    "collection_date_precision",
+    "lat",
+    "latitude",
+    "lon",
+    "longitude",
+    "depth",
+    "temperature",
+    "salinity",
+    "oxygen",
+    "oxygen_unit",
+    "nitrate",
+    "nitrate_unit",
+    "phosphate",
+    "phosphate_unit",
+    "silicate",
+    "silicate_unit",
+    "chlorophyll",
+    "env_broad_scale",
+    "env_broad_scale_id",
+    "env_local_scale",
+    "env_local_scale_id",
+    "env_medium",
+    "env_medium_id",
+    "runs",
+    "experiments",
+    "library_strategy",
+    "library_source",
+    "library_layout",
+    "platform",
+    "instrument_model",
+    "checklist",
+    "spot_count",
+    "base_count",
+    "first_public",
+    "last_update",
+    "studies",
+    "bioproject",
+    "study_title",
+    "study_abstract",
+    "study_center",
+    "station",
+    "size_fraction",
+    "size_fraction_lower",
+    "size_fraction_upper",
+    "inferred",
+    "warnings",
+];
+
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    out: Option<String>,
+    sqlite: Option<String>,
+    format: Format,
+    partition_by: Option<PartitionBy>,
+    primary_study: bool,
+    long: bool,
+    wide: bool,
+    min_coverage: f64,
+    include_ena: bool,
+    columns: Vec<Column>,
+    delimiter: u8,
+    quote: QuoteStyle,
+    na: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Format {
+    /// With the file extension, "tsv" or "csv", whatever the delimiter
+    Delimited(&'static str),
+    Parquet,
+    GeoJson,
+    Dwca,
+    Xlsx,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Field(String),
+    Attr(String),
+}
+
+pub trait SampleWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()>;
+    fn finish(&mut self) -> MyResult<()>;
+}
+
+struct DelimitedWriter {
+    writer: Writer<Box<dyn Write>>,
+    columns: Vec<Column>,
+    na: String,
+}
+
+/// One row per sample attribute: accession, tag, value, units, position
+struct LongWriter {
+    writer: Writer<Box<dyn Write>>,
+    include_ena: bool,
+    na: String,
+}
+
+// --------------------------------------------------
+pub fn get_config(matches: &ArgMatches) -> MyResult<ExportConfig> {
+    let format = matches.value_of("format").unwrap_or("tsv");
+    let delimiter = match matches.value_of("delimiter") {
+        Some(d) => parse_delimiter(d)?,
+        _ if format == "csv" => b',',
+        _ => b'\t',
+    };
+
+    let quote = match matches.value_of("quote") {
+        Some("always") => QuoteStyle::Always,
+        Some("never") => QuoteStyle::Never,
+        Some("non-numeric") => QuoteStyle::NonNumeric,
+        _ => QuoteStyle::Necessary,
+    };
+
+    let long = matches.is_present("long");
+    let wide = matches.is_present("wide");
+    if (long || wide) && !(format == "tsv" || format == "csv") {
+        return Err(From::from("--long and --wide are only for tsv or csv"));
+    }
+
+    let partition_by = match matches.value_of("partition_by") {
+        Some("study") => Some(PartitionBy::Study),
+        Some("taxon") => Some(PartitionBy::Taxon),
+        Some("size_fraction") => Some(PartitionBy::SizeFraction),
+        _ => None,
+    };
+    if partition_by.is_some() && (wide || format != "tsv" && format != "csv") {
+        return Err(From::from(
+            "--partition-by is only for tsv or csv and not --wide",
+        ));
+    }
+
+    let min_coverage = match matches.value_of("min_coverage") {
+        Some(val) => match val.parse::<f64>() {
+            Ok(n) if (0. ..=1.).contains(&n) => n,
+            _ => {
+                return Err(From::from(format!(
+                    "--min-coverage \"{}\" must be between 0 and 1",
+                    val
+                )))
+            }
+        },
+        _ => 0.,
+    };
+
+    Ok(ExportConfig {
+        out: matches.value_of("out").map(|s| s.to_string()),
+        sqlite: matches.value_of("sqlite").map(|s| s.to_string()),
+        format: match format {
+            "parquet" => Format::Parquet,
+            "geojson" => Format::GeoJson,
+            "dwca" => Format::Dwca,
+            "xlsx" => Format::Xlsx,
+            "csv" => Format::Delimited("csv"),
+            _ => Format::Delimited("tsv"),
+        },
+        partition_by,
+        primary_study: matches.is_present("primary_study"),
+        long,
+        wide,
+        min_coverage,
+        include_ena: matches.is_present("include_ena"),
+        columns: parse_columns(matches.value_of("columns").unwrap_or(DEFAULT_COLUMNS))?,
+        delimiter,
+        quote,
+        na: matches.value_of("na").unwrap_or("").to_string(),
+    })
+}
+
+// --------------------------------------------------
+/// The export without options: the default columns as TSV on STDOUT
+pub fn default_config() -> MyResult<ExportConfig> {
+    Ok(ExportConfig {
+        out: None,
+        sqlite: None,
+        format: Format::Delimited("tsv"),
+        partition_by: None,
+        primary_study: false,
+        long: false,
+        wide: false,
+        min_coverage: 0.,
+        include_ena: false,
+        columns: parse_columns(DEFAULT_COLUMNS)?,
+        delimiter: b'\t',
+        quote: QuoteStyle::Necessary,
+        na: String::new(),
+    })
+}
+
+// --------------------------------------------------
+/// Opens the writer for the configured format, recording provenance in
+/// the way that suits it: a comment header for delimited text (and a
+/// provenance.json for partitions), and metadata for the rest
+pub fn open_writer(config: &ExportConfig, prov: &Provenance) -> MyResult<Box<dyn SampleWriter>> {
+    if let Some(db) = &config.sqlite {
+        return Ok(Box::new(SqliteWriter::open(db, prov)?));
+    }
+
+    match (&config.format, &config.out) {
+        (Format::Parquet, Some(path)) => return Ok(Box::new(ParquetWriter::open(path, prov)?)),
+        (Format::Dwca, Some(path)) => return Ok(Box::new(DwcaWriter::open(path, prov)?)),
+        (Format::Xlsx, Some(path)) => {
+            return Ok(Box::new(XlsxWriter::open(path, &config.columns, prov)?))
+        }
+        _ => (),
+    }
+
+    if let (Some(by), Some(dir)) = (&config.partition_by, &config.out) {
+        fs::create_dir_all(dir)?;
+        let sidecar = File::create(Path::new(dir).join("provenance.json"))?;
+        ::serde_json::to_writer_pretty(sidecar, &prov.to_json())?;
+
+        let table_config = config.clone();
+        let comments = prov.comment_lines();
+        return Ok(Box::new(PartitionWriter::new(
+            dir,
+            match config.format {
+                Format::Delimited(ext) => ext,
+                _ => "tsv",
+            },
+            by.clone(),
+            config.primary_study,
+            Box::new(move |mut out, header| {
+                if header {
+                    for line in &comments {
+                        writeln!(out, "{}", line)?;
+                    }
+                }
+                open_table(&table_config, out, header)
+            }),
+        )));
+    }
+
+    let mut out: Box<dyn Write> = match &config.out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        _ => Box::new(io::stdout()),
+    };
+
+    if config.format == Format::GeoJson {
+        return Ok(Box::new(GeoJsonWriter::new(out, prov)?));
+    }
+
+    for line in prov.comment_lines() {
+        writeln!(out, "{}", line)?;
+    }
+
+    if config.wide {
+        let writer = WriterBuilder::new()
+            .delimiter(config.delimiter)
+            .quote_style(config.quote)
+            .from_writer(out);
+        return Ok(Box::new(WideWriter::new(
+            writer,
+            config.min_coverage,
+            config.include_ena,
+            &config.na,
+        )?));
+    }
+
+    open_table(config, out, true)
+}
+
+// --------------------------------------------------
+/// A row-per-sample (or, with --long, per-attribute) table; the header
+/// is skipped when appending to a partition that was closed earlier
+fn open_table(
+    config: &ExportConfig,
+    out: Box<dyn Write>,
+    header: bool,
+) -> MyResult<Box<dyn SampleWriter>> {
+    let mut writer = WriterBuilder::new()
+        .delimiter(config.delimiter)
+        .quote_style(config.quote)
+        .from_writer(out);
+
+    if config.long {
+        if header {
+            writer.write_record(["accession", "tag", "value", "units", "position"])?;
+        }
+        return Ok(Box::new(LongWriter {
+            writer,
+            include_ena: config.include_ena,
+            na: config.na.to_string(),
+        }));
+    }
+
+    if header {
+        writer.write_record(config.columns.iter().map(column_name))?;
+    }
+
+    Ok(Box::new(DelimitedWriter {
+        writer,
+        columns: config.columns.clone(),
+        na: config.na.to_string(),
+    }))
+}
+
+impl SampleWriter for DelimitedWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        let na = &self.na;
+        let row: Vec<String> = self
+            .columns
+            .iter()
+            .map(|c| column_value(sample, c).unwrap_or_else(|| na.to_string()))
+            .collect();
+        self.writer.write_record(&row)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl SampleWriter for LongWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        let mut attrs: Vec<&Attr> = sample.attributes.iter().collect();
+        if self.include_ena {
+            attrs.extend(sample.ena_attributes.iter());
+            attrs.sort_by_key(|attr| attr.position);
+        }
+
+        for attr in attrs {
+            self.writer.write_record([
+                sample.accession.as_str(),
+                &attr.tag,
+                &attr.value,
+                attr.units.as_ref().unwrap_or(&self.na),
+                &attr.position.to_string(),
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn parse_delimiter(val: &str) -> MyResult<u8> {
+    match val {
+        "\\t" | "tab" => Ok(b'\t'),
+        d if d.len() == 1 => Ok(d.as_bytes()[0]),
+        _ => Err(From::from(format!(
+            "Delimiter \"{}\" must be a single character",
+            val
+        ))),
+    }
+}
+
+// --------------------------------------------------
+pub fn parse_columns(val: &str) -> MyResult<Vec<Column>> {
+    let mut columns = vec![];
+    for name in val.split(',').map(|c| c.trim()).filter(|c| !c.is_empty()) {
+        if let Some(tag) = name.strip_prefix("attr:") {
+            columns.push(Column::Attr(tag.to_string()));
+        } else if FIELDS.contains(&name) {
+            columns.push(Column::Field(name.to_string()));
+        } else {
+            return Err(From::from(format!(
+                "Unknown column \"{}\", choose from {} or attr:<TAG>",
+                name,
+                FIELDS.join(", ")
+            )));
+        }
+    }
+
+    if columns.is_empty() {
+        return Err(From::from("No columns"));
+    }
+
+    Ok(columns)
+}
+
+// --------------------------------------------------
+pub fn column_name(column: &Column) -> String {
+    match column {
+        Column::Field(name) => name.to_string(),
+        Column::Attr(tag) => tag.to_string(),
+    }
+}
+
+// --------------------------------------------------
+fn join(vals: Vec<String>) -> Option<String> {
+    let mut uniq: Vec<String> = vec![];
+    for val in vals {
+        if !uniq.contains(&val) {
+            uniq.push(val);
+        }
+    }
+
+    if uniq.is_empty() {
+        None
+    } else {
+        Some(uniq.join(","))
+    }
+}
+
+// --------------------------------------------------
+pub fn column_value(sample: &Sample, column: &Column) -> Option<String> {
+    match column {
+        Column::Attr(tag) => sample
+            .attributes
+            .iter()
+            .chain(sample.ena_attributes.iter())
+            .find(|a| &a.tag == tag)
+            .map(|a| a.value.to_string()),
+        Column::Field(name) => field_value(sample, name),
+    }
+}
+
+// --------------------------------------------------
+fn field_value(sample: &Sample, name: &str) -> Option<String> {
+    let experiment = |f: &dyn Fn(&super::experiment::Experiment) -> Option<String>| {
+        join(
+            sample
+                .run_info
+                .iter()
+                .filter_map(|i| i.experiment.as_ref())
+                .filter_map(f)
+                .collect(),
+        )
+    };
+
+    let study = |f: &dyn Fn(&super::study::Study) -> Option<String>| {
+        join(sample.study_info.iter().filter_map(f).collect())
+    };
+
+    let quantity = |field: &str| sample.quantities.iter().find(|q| q.field == field);
+
+    let env = |field: &str| {
+        let field = field.to_string();
+        sample.env_terms.iter().filter(move |t| t.field == field)
+    };
+
+    match name {
+        "accession" | "sample" => Some(sample.accession.to_string()),
+        "biosample" => sample.biosample.clone(),
+        "title" => sample.title.clone(),
+        "description" => sample.description.clone(),
+        "taxon_id" => sample.taxon_id.clone(),
+        "scientific_name" => sample.scientific_name.clone(),
+        "common_name" => sample.common_name.clone(),
+        "lineage" | "lineage_ids" if sample.lineage.is_empty() => None,
+        "lineage" => Some(
+            sample
+                .lineage
+                .iter()
+                .map(|t| t.name.to_string())
+                .collect::<Vec<String>>()
+                .join("; "),
+        ),
+        "lineage_ids" => Some(
+            sample
+                .lineage
+                .iter()
+                .map(|t| t.id.to_string())
+                .collect::<Vec<String>>()
+                .join(";"),
+        ),
+        rank if RANKS.contains(&rank) => {
+            taxonomy::at_rank(&sample.lineage, rank).map(|t| t.name.to_string())
+        }
+        "collection_date" => sample.collection_date().map(|d| d.to_precise_string()),
+        "collection_date_tag" => sample.collection_date().map(|d| d.tag.to_string()),
+        "collection_date_precision" => sample.collection_date().map(|d| d.precision.to_string()),
+        "lat" | "latitude" => sample.lat_lon.map(|(lat, _)| lat.to_string()),
+        "lon" | "longitude" => sample.lat_lon.map(|(_, lon)| lon.to_string()),
+        "depth" => sample.depth.map(|d| d.to_string()),
+        "runs" => join(sample.runs.clone()),
+        "experiments" => join(sample.experiments.clone()),
+        "library_strategy" => experiment(&|e| e.library_strategy.clone()),
+        "library_source" => experiment(&|e| e.library_source.clone()),
+        "library_layout" => experiment(&|e| e.library_layout.clone()),
+        "platform" => experiment(&|e| e.platform.clone()),
+        "instrument_model" => experiment(&|e| e.instrument_model.clone()),
+        "checklist" => sample.ena.checklist.clone(),
+        "spot_count" => sample.ena.spot_count.map(|n| n.to_string()),
+        "base_count" => sample.ena.base_count.map(|n| n.to_string()),
+        "first_public" => sample.ena.first_public.map(|d| d.to_string()),
+        "last_update" => sample.ena.last_update.map(|d| d.to_string()),
+        "studies" => join(sample.studies.clone()),
+        "bioproject" => study(&|s| s.bioproject.clone()),
+        "study_title" => study(&|s| s.title.clone()),
+        "study_abstract" => study(&|s| s.study_abstract.clone()),
+        "study_center" => study(&|s| s.center.clone()),
+        "temperature" | "salinity" | "oxygen" | "nitrate" | "phosphate" | "silicate"
+        | "chlorophyll" => quantity(name).map(|q| q.value.to_string()),
+        "oxygen_unit" | "nitrate_unit" | "phosphate_unit" | "silicate_unit" => {
+            quantity(name.trim_end_matches("_unit")).map(|q| q.unit.to_string())
+        }
+        "env_broad_scale" | "env_local_scale" | "env_medium" => {
+            join(env(name).filter_map(|t| t.label.clone()).collect())
+        }
+        "env_broad_scale_id" | "env_local_scale_id" | "env_medium_id" => join(
+            env(name.trim_end_matches("_id"))
+                .filter_map(|t| t.id.clone())
+                .collect(),
+        ),
+        "size_fraction" => sample.size_fraction.as_ref().map(|f| f.label()),
+        "size_fraction_lower" => sample
+            .size_fraction
+            .as_ref()
+            .and_then(|f| f.lower)
+            .map(|n| n.to_string()),
+        "size_fraction_upper" => sample
+            .size_fraction
+            .as_ref()
+            .and_then(|f| f.upper)
+            .map(|n| n.to_string()),
+        "station" => sample
+            .inferred
+            .iter()
+            .find(|i| i.field == name)
+            .map(|i| i.value.to_string()),
+        "inferred" => join(
+            sample
+                .inferred
+                .iter()
+                .map(|i| format!("{}={} [{}]", i.field, i.value, i.source))
+                .collect(),
+        ),
+        "warnings" => {
+            let warnings: Vec<String> = sample
+                .warnings
+                .iter()
+                .map(|w| format!("{}: {}", w.field, w.message))
+                .collect();
+            if warnings.is_empty() {
+                None
+            } else {
+                Some(warnings.join("; "))
+            }
+        }
+        _ => None,
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_parse_columns() {
+    assert_eq!(
+        parse_columns("accession, lat,attr:Salinity Sensor").unwrap(),
+        vec![
+            Column::Field("accession".to_string()),
+            Column::Field("lat".to_string()),
+            Column::Attr("Salinity Sensor".to_string()),
+        ]
+    );
+    assert!(parse_columns("accession,foo").is_err());
+    assert!(parse_columns("").is_err());
+    assert_eq!(parse_delimiter("\\t").unwrap(), b'\t');
+    assert_eq!(parse_delimiter("|").unwrap(), b'|');
+    assert!(parse_delimiter("||").is_err());
+}
+
+// --------------------------------------------------
+#[test]
+fn test_column_values() {
+    let root = ::xmltree::Element::parse(File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let sample = super::parse_xml(root, &[]).unwrap();
+    let value = |name: &str| {
+        let column = parse_columns(name).unwrap().pop().unwrap();
+        column_value(&sample, &column)
+    };
+
+    assert_eq!(value("accession"), Some("ERS494529".to_string()));
+    assert_eq!(value("biosample"), Some("SAMEA2623861".to_string()));
+    assert_eq!(value("taxon_id"), Some("408172".to_string()));
+    assert_eq!(
+        value("collection_date"),
+        Some("2012-03-09T08:59".to_string())
+    );
+    assert_eq!(value("lat"), Some("36.1715".to_string()));
+    assert_eq!(value("lon"), Some("-29.023".to_string()));
+    assert_eq!(value("depth"), Some("5".to_string()));
+    assert_eq!(
+        value("runs"),
+        Some("ERR868459,ERR1718757,ERR1719440,ERR1756353".to_string())
+    );
+    assert_eq!(value("attr:Salinity Sensor"), Some("36.23775".to_string()));
+    assert_eq!(value("attr:Nope"), None);
+    assert_eq!(value("attr:ENA-CHECKLIST"), Some("ERC000030".to_string()));
+    assert_eq!(value("study_title"), None);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_long_writer() {
+    let root = ::xmltree::Element::parse(File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let sample = super::parse_xml(root, &[]).unwrap();
+    let path = ::std::env::temp_dir().join(format!("mextract-{}.tsv", ::std::process::id()));
+    let path = path.to_str().unwrap();
+
+    for include_ena in &[false, true] {
+        let mut writer = LongWriter {
+            writer: WriterBuilder::new()
+                .delimiter(b'\t')
+                .from_writer(Box::new(File::create(path).unwrap())),
+            include_ena: *include_ena,
+            na: "NA".to_string(),
+        };
+        writer.write(&sample).unwrap();
+        writer.finish().unwrap();
+
+        let rows = ::std::fs::read_to_string(path).unwrap();
+        let depth = rows.lines().find(|l| l.contains("\tDepth\t")).unwrap();
+        assert_eq!(depth, "ERS494529\tDepth\t5\tm\t11");
+        assert_eq!(rows.contains("ENA-CHECKLIST"), *include_ena);
+    }
+    ::std::fs::remove_file(path).unwrap();
+}
+
+// --------------------------------------------------
+#[test]
+fn test_partition_extension() {
+    let dir = ::std::env::temp_dir().join(format!("mextract-ext-{}", ::std::process::id()));
+    let config = ExportConfig {
+        out: Some(dir.to_str().unwrap().to_string()),
+        format: Format::Delimited("csv"),
+        delimiter: b';',
+        partition_by: Some(PartitionBy::Taxon),
+        ..default_config().unwrap()
+    };
+
+    let prov = super::provenance::get_provenance(&[]).unwrap();
+    let mut writer = open_writer(&config, &prov).unwrap();
+    writer.write(&super::tara_sample()).unwrap();
+    writer.finish().unwrap();
+
+    // the format names the file, not the delimiter
+    let rows = ::std::fs::read_to_string(dir.join("408172.csv")).unwrap();
+    assert!(rows.contains("ERS494529;"));
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
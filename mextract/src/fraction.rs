@@ -0,0 +1,357 @@
+use regex::Regex;
+
+use super::quantity::{convert, split_number};
+use super::{Attr, Sample, Warning};
+
+/// Tags for the lower and upper bounds as separate attributes
+const LOWER_TAGS: &[&str] = &[
+    "size fraction lower threshold",
+    "size_frac_low",
+    "size fraction lower",
+];
+const UPPER_TAGS: &[&str] = &[
+    "size fraction upper threshold",
+    "size_frac_up",
+    "size fraction upper",
+];
+
+/// Tags for a range such as "0.22-3 µm" in one attribute
+const RANGE_TAGS: &[&str] = &[
+    "size_frac",
+    "size fraction",
+    "size fraction selected",
+    "filter size",
+    "filter pore size",
+    "filter_size",
+];
+
+/// A protocol code like "W0.8-5", "W>0.8" or "W<5" ending a label or one
+/// of its "_"-separated parts, with the range in the group "size"
+pub const PROTOCOL_CODE: &str = r"W(?P<size>[<>]?\d*\.?\d+(?:-\d*\.?\d+)?)(?:_|$)";
+
+/// Tags (besides TITLE) of labels that may hold a "_W0.8-5" protocol code
+const LABEL_TAGS: &[&str] = &["protocol label", "event label", "sample label"];
+
+/// Lower and upper sizes in micrometres, either of which may be open
+type Bounds = (Option<f64>, Option<f64>);
+
+/// Filter size bounds in micrometres; either may be open
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeFraction {
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub source: String,
+}
+
+impl SizeFraction {
+    /// "0.8-5", ">0.8" or "<0.22"
+    pub fn label(&self) -> String {
+        match (self.lower, self.upper) {
+            (Some(lower), Some(upper)) => format!("{}-{}", lower, upper),
+            (Some(lower), _) => format!(">{}", lower),
+            (_, Some(upper)) => format!("<{}", upper),
+            _ => String::new(),
+        }
+    }
+
+    /// A file-safe partition key: "0.8-5", "gt0.8" or "lt0.22"
+    pub fn key(&self) -> String {
+        match (self.lower, self.upper) {
+            (Some(lower), Some(upper)) => format!("{}-{}", lower, upper),
+            (Some(lower), _) => format!("gt{}", lower),
+            (_, Some(upper)) => format!("lt{}", upper),
+            _ => String::new(),
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        (self.lower, self.upper)
+    }
+}
+
+// --------------------------------------------------
+/// Finds the size fraction in threshold attributes, range attributes,
+/// protocol labels (including TITLE), label decoders and DESCRIPTION, in
+/// that order of preference; sources that disagree with the one used are
+/// warned about, as is a lone size taken as the lower bound
+pub fn get_size_fraction(sample: &Sample) -> (Option<SizeFraction>, Vec<Warning>) {
+    // with whether the source gave one size and not a range
+    let mut found: Vec<(SizeFraction, Option<usize>, bool)> = vec![];
+    let tag_in = |attr: &Attr, tags: &[&str]| tags.contains(&attr.tag.to_lowercase().as_str());
+
+    // Separate lower and upper thresholds
+    let bound = |tags: &[&str]| {
+        sample
+            .attributes
+            .iter()
+            .find(|a| tag_in(a, tags))
+            .and_then(|a| parse_size(&a.value, a.units.as_deref()).map(|n| (n, a)))
+    };
+    let (lower, upper) = (bound(LOWER_TAGS), bound(UPPER_TAGS));
+    if let Some((_, attr)) = lower.or(upper) {
+        found.push((
+            SizeFraction {
+                lower: lower.map(|(n, _)| n),
+                upper: upper.map(|(n, _)| n),
+                source: attr.tag.to_string(),
+            },
+            Some(attr.position),
+            false,
+        ));
+    }
+
+    // One attribute with a range
+    for attr in sample.attributes.iter().filter(|a| tag_in(a, RANGE_TAGS)) {
+        if let Some(((lower, upper), lone)) = parse_bounds(&attr.value, attr.units.as_deref()) {
+            found.push((fraction(lower, upper, &attr.tag), Some(attr.position), lone));
+        }
+    }
+
+    // Protocol codes like "_W0.8-5" or "_W>0.8" in labels
+    let code_re = Regex::new(&format!("(?:^|_){}", PROTOCOL_CODE)).unwrap();
+    let labels = sample
+        .title
+        .iter()
+        .map(|title| ("TITLE", title, None))
+        .chain(
+            sample
+                .attributes
+                .iter()
+                .filter(|a| tag_in(a, LABEL_TAGS))
+                .map(|a| (a.tag.as_str(), &a.value, Some(a.position))),
+        );
+    for (source, label, position) in labels {
+        if let Some(caps) = code_re.captures(label) {
+            if let Some(((lower, upper), lone)) = parse_bounds(&caps["size"], None) {
+                found.push((fraction(lower, upper, source), position, lone));
+            }
+        }
+    }
+
+    // Ranges that label decoders found
+    for inferred in sample
+        .inferred
+        .iter()
+        .filter(|i| i.field == "size_fraction")
+    {
+        if let Some(((lower, upper), lone)) = parse_bounds(&inferred.value, None) {
+            found.push((fraction(lower, upper, &inferred.source), None, lone));
+        }
+    }
+
+    // "size-fractionated (0.8-5 micrometres)"
+    let desc_re = Regex::new(
+        r"(?xi)
+        size[-\ ]fraction(?:ated)?\s*(?:of\s*)?\(?
+        (?P<range>
+            [<>]?\s*\d*\.?\d+
+            (?:\s*-\s*\d*\.?\d+)?
+            \s*(?:µm|um|microns?|micrometers?|micrometres?|mm|nm)?
+        )
+        ",
+    )
+    .unwrap();
+    if let Some(caps) = sample
+        .description
+        .as_ref()
+        .and_then(|d| desc_re.captures(d))
+    {
+        if let Some(((lower, upper), lone)) = parse_bounds(&caps["range"], None) {
+            found.push((fraction(lower, upper, "DESCRIPTION"), None, lone));
+        }
+    }
+
+    let mut found = found.into_iter();
+    let (best, mut warnings) = match found.next() {
+        Some((best, position, true)) => {
+            let warning = Warning {
+                field: "size_fraction".to_string(),
+                message: format!(
+                    "{} gives one size, taken as the lower bound {} µm",
+                    best.source,
+                    best.label()
+                ),
+                position,
+            };
+            (best, vec![warning])
+        }
+        Some((best, _, _)) => (best, vec![]),
+        _ => return (None, vec![]),
+    };
+
+    warnings.extend(
+        found
+            .filter(|(other, _, _)| other.bounds() != best.bounds())
+            .map(|(other, position, _)| Warning {
+                field: "size_fraction".to_string(),
+                message: format!(
+                    "{} says {} µm but {} says {} µm",
+                    other.source,
+                    other.label(),
+                    best.source,
+                    best.label()
+                ),
+                position,
+            }),
+    );
+
+    (Some(best), warnings)
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn fraction(lower: Option<f64>, upper: Option<f64>, source: &str) -> SizeFraction {
+    SizeFraction {
+        lower,
+        upper,
+        source: source.to_string(),
+    }
+}
+
+// --------------------------------------------------
+/// A size in micrometres, taking a bare number as micrometres
+fn parse_size(val: &str, units: Option<&str>) -> Option<f64> {
+    let (num, unit) = split_number(val)?;
+    let unit = if unit.is_empty() {
+        units.unwrap_or("")
+    } else {
+        unit
+    };
+    if unit.is_empty() {
+        Some(num)
+    } else {
+        convert(num, unit, "µm")
+    }
+}
+
+// --------------------------------------------------
+/// Reads a range or else a lone size as the lower bound (i.e., what the
+/// filter retains), saying which it was
+fn parse_bounds(val: &str, units: Option<&str>) -> Option<(Bounds, bool)> {
+    match parse_range(val, units) {
+        Some(bounds) => Some((bounds, false)),
+        _ => parse_size(val.trim(), units).map(|n| ((Some(n), None), true)),
+    }
+}
+
+// --------------------------------------------------
+/// Reads "0.22-3 µm", "0.8 to 5", ">0.8" or "<0.22"
+fn parse_range(val: &str, units: Option<&str>) -> Option<Bounds> {
+    let range_re = Regex::new(
+        r"(?x)
+        ^\s*
+        (?P<min>\d*\.?\d+)
+        \s*(?P<min_unit>[[:alpha:]µ]+)?
+        \s*(?:-|–|to)\s*
+        (?P<max>\d*\.?\d+)
+        \s*(?P<unit>.*?)\s*$
+        ",
+    )
+    .unwrap();
+
+    let val = val.trim();
+    if let Some(caps) = range_re.captures(val) {
+        let unit = caps.name("unit").map_or("", |u| u.as_str());
+        let unit = if unit.is_empty() {
+            units.unwrap_or("")
+        } else {
+            unit
+        };
+        let min_unit = caps.name("min_unit").map_or(unit, |u| u.as_str());
+        let min = parse_size(&format!("{} {}", &caps["min"], min_unit), None)?;
+        let max = parse_size(&format!("{} {}", &caps["max"], unit), None)?;
+        return Some((Some(min), Some(max)));
+    }
+
+    if let Some(rest) = val.strip_prefix('>') {
+        return parse_size(rest.trim(), units).map(|n| (Some(n), None));
+    }
+
+    if let Some(rest) = val.strip_prefix('<') {
+        return parse_size(rest.trim(), units).map(|n| (None, Some(n)));
+    }
+
+    None
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("0.22-3 µm", None), Some((Some(0.22), Some(3.))));
+    assert_eq!(parse_range("0.8 to 5", None), Some((Some(0.8), Some(5.))));
+    assert_eq!(
+        parse_range("0.8-5", Some("um")),
+        Some((Some(0.8), Some(5.)))
+    );
+    assert_eq!(parse_range(">0.8", None), Some((Some(0.8), None)));
+    assert_eq!(
+        parse_range("< 0.22 micrometres", None),
+        Some((None, Some(0.22)))
+    );
+    assert_eq!(
+        parse_range("180 µm - 2 mm", None),
+        Some((Some(180.), Some(2000.)))
+    );
+    assert_eq!(parse_range("0.22 microns", None), None);
+    assert_eq!(
+        parse_bounds("0.22 microns", None),
+        Some(((Some(0.22), None), true))
+    );
+    assert_eq!(
+        parse_bounds(">0.22", None),
+        Some(((Some(0.22), None), false))
+    );
+    assert_eq!(parse_range("0.2 kg", None), None);
+    assert_eq!(parse_range("whole", None), None);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_get_size_fraction() {
+    let sample = super::tara_sample();
+    let size = sample.size_fraction.as_ref().unwrap();
+    assert_eq!((size.lower, size.upper), (Some(0.8), Some(5.)));
+    assert_eq!(size.source, "Size Fraction Lower Threshold");
+    assert_eq!(size.label(), "0.8-5");
+    assert!(!sample.warnings.iter().any(|w| w.field == "size_fraction"));
+
+    let attr = |tag: &str, value: &str| super::make_attr(tag, value, 4);
+
+    let mut sample = Sample {
+        title: Some("TARA_X_W>0.8_Y".to_string()),
+        attributes: vec![attr("filter size", "0.22-3 µm")],
+        ..Default::default()
+    };
+    let (size, warnings) = get_size_fraction(&sample);
+    assert_eq!(size.unwrap().label(), "0.22-3");
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        warnings[0].message,
+        "TITLE says >0.8 µm but filter size says 0.22-3 µm"
+    );
+
+    sample.attributes = vec![attr("Protocol Label", "PROT_NUC-DNA(10L)_W0.22-1.6")];
+    sample.title = None;
+    let (size, warnings) = get_size_fraction(&sample);
+    let size = size.unwrap();
+    assert_eq!((size.lower, size.upper), (Some(0.22), Some(1.6)));
+    assert_eq!(size.source, "Protocol Label");
+    assert!(warnings.is_empty());
+
+    // a lone size is the lower bound, but says so
+    sample.attributes = vec![attr("filter size", "0.22")];
+    let (size, warnings) = get_size_fraction(&sample);
+    let size = size.unwrap();
+    assert_eq!((size.lower, size.upper), (Some(0.22), None));
+    assert_eq!(size.key(), "gt0.22");
+    assert_eq!(
+        warnings[0].message,
+        "filter size gives one size, taken as the lower bound >0.22 µm"
+    );
+    assert_eq!(warnings[0].position, Some(4));
+
+    assert!(get_size_fraction(&Sample::default()).0.is_none());
+}
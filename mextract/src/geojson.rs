@@ -0,0 +1,137 @@
+use regex::Regex;
+use serde_json::{json, Value};
+use std::io::Write;
+
+use super::export::SampleWriter;
+use super::provenance::Provenance;
+use super::{parse_coord, Attr, MyResult, Sample};
+
+/// Writes a FeatureCollection with a feature for each sample that has
+/// coordinates, streaming one feature per line
+pub struct GeoJsonWriter {
+    out: Box<dyn Write>,
+    count: usize,
+}
+
+impl GeoJsonWriter {
+    pub fn new(mut out: Box<dyn Write>, prov: &Provenance) -> MyResult<GeoJsonWriter> {
+        // provenance goes in a foreign member of the collection
+        writeln!(
+            out,
+            r#"{{"type": "FeatureCollection", "provenance": {}, "features": ["#,
+            prov.to_json()
+        )?;
+        Ok(GeoJsonWriter { out, count: 0 })
+    }
+}
+
+impl SampleWriter for GeoJsonWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        if let Some(feature) = sample_feature(sample) {
+            if self.count > 0 {
+                writeln!(self.out, ",")?;
+            }
+            write!(self.out, "{}", feature)?;
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        writeln!(self.out, "\n]}}")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+/// A Point at the sample's coordinates (or the start of its transect),
+/// collected with the start/end transect as a LineString when there is one
+fn sample_feature(sample: &Sample) -> Option<Value> {
+    let transect = get_transect(&sample.attributes);
+    let (lat, lon) = sample.lat_lon.or(transect.map(|(start, _)| start))?;
+    let point = json!({ "type": "Point", "coordinates": [lon, lat] });
+    let geometry = match transect {
+        Some(((lat1, lon1), (lat2, lon2))) => json!({
+            "type": "GeometryCollection",
+            "geometries": [
+                point,
+                { "type": "LineString", "coordinates": [[lon1, lat1], [lon2, lat2]] },
+            ],
+        }),
+        _ => point,
+    };
+
+    Some(json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": {
+            "accession": sample.accession,
+            "collection_date": sample.collection_date().map(|d| d.to_precise_string()),
+            "depth": sample.depth,
+            "runs": sample.runs.len(),
+        },
+    }))
+}
+
+// --------------------------------------------------
+/// Start and end (lat, lon) from "Latitude Start", "Longitude End", etc.
+fn get_transect(attrs: &[Attr]) -> Option<((f64, f64), (f64, f64))> {
+    let tag_re = Regex::new(r"(?i)^(lat|long?)(?:itude)?[\s_](start|end)$").unwrap();
+    let (mut lat1, mut lon1, mut lat2, mut lon2) = (None, None, None, None);
+    for attr in attrs {
+        if let Some(caps) = tag_re.captures(&attr.tag) {
+            let is_lat = caps[1].eq_ignore_ascii_case("lat");
+            let coord = parse_coord(&attr.value, if is_lat { 90. } else { 180. });
+            match (is_lat, caps[2].eq_ignore_ascii_case("start")) {
+                (true, true) => lat1 = lat1.or(coord),
+                (false, true) => lon1 = lon1.or(coord),
+                (true, false) => lat2 = lat2.or(coord),
+                (false, false) => lon2 = lon2.or(coord),
+            }
+        }
+    }
+
+    Some(((lat1?, lon1?), (lat2?, lon2?)))
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_sample_feature() {
+    let mut sample = super::tara_sample();
+
+    let feature = sample_feature(&sample).unwrap();
+    let geometries = &feature["geometry"]["geometries"];
+    assert_eq!(feature["geometry"]["type"], "GeometryCollection");
+    assert_eq!(geometries[0]["type"], "Point");
+    assert_eq!(geometries[0]["coordinates"], json!([-29.023, 36.1715]));
+    assert_eq!(geometries[1]["type"], "LineString");
+    assert_eq!(
+        geometries[1]["coordinates"],
+        json!([[-29.023, 36.1715], [-28.9598, 36.139]])
+    );
+    assert_eq!(feature["properties"]["accession"], "ERS494529");
+    assert_eq!(feature["properties"]["collection_date"], "2012-03-09T08:59");
+    assert_eq!(feature["properties"]["runs"], 4);
+
+    // the transect start stands in for missing coordinates
+    sample.lat_lon = None;
+    let feature = sample_feature(&sample).unwrap();
+    assert_eq!(
+        feature["geometry"]["geometries"][0]["coordinates"],
+        json!([-29.023, 36.1715])
+    );
+
+    // without a transect, the Point alone
+    let mut sample = super::tara_sample();
+    sample.attributes.retain(|a| !a.tag.ends_with(" End"));
+    let feature = sample_feature(&sample).unwrap();
+    assert_eq!(feature["geometry"]["type"], "Point");
+
+    sample.lat_lon = None;
+    assert!(sample_feature(&sample).is_none());
+}
@@ -1,64 +1,233 @@
+extern crate arrow_array;
 extern crate chrono;
 extern crate clap;
+extern crate csv;
+extern crate md5;
+extern crate parquet;
 extern crate regex;
-extern crate time;
+extern crate rusqlite;
+extern crate rust_xlsxwriter;
+extern crate serde_json;
+extern crate sha2;
 extern crate xmltree;
+extern crate zip;
 
-use chrono::{DateTime, TimeZone, Utc}; // Date
-use clap::{App, Arg};
+use chrono::{DateTime, Duration, TimeZone, Utc}; // Date
+use clap::{App, AppSettings, Arg, SubCommand};
 use regex::{Captures, Regex, RegexSet};
 use std::error::Error;
 use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
 use std::str::FromStr;
-use std::collections::HashMap;
-use time::Duration;
 use xmltree::Element;
 
+mod biosample;
+mod checklist;
+mod columnar;
+mod decode;
+mod description;
+mod dwca;
+mod ena;
+mod envo;
+mod experiment;
+mod export;
+mod fraction;
+mod geojson;
+mod partition;
+mod pivot;
+mod provenance;
+mod quantity;
+mod sqlite;
+mod sra;
+mod study;
+mod taxon;
+mod taxonomy;
+mod verify;
+mod xlsx;
+
 #[derive(Debug)]
 pub struct Config {
     input: Vec<String>,
+    command: Command,
+    experiments: Vec<String>,
+    runs: Vec<String>,
+    run_filter: experiment::RunFilter,
+    ena_filter: ena::EnaFilter,
+    studies: Vec<String>,
+    taxon_filter: taxon::TaxonFilter,
+    taxonomy: Option<String>,
+    taxonomy_cache: Option<String>,
+    envo: Option<String>,
+    decoders: Vec<Box<dyn decode::LabelDecoder>>,
+    units: Vec<(&'static str, &'static str)>,
 }
 
 #[derive(Debug)]
+enum Command {
+    Extract,
+    Export(export::ExportConfig),
+    VerifyReads(verify::VerifyConfig),
+    Validate(checklist::ValidateConfig),
+}
+
+#[derive(Debug, Default)]
 struct Attr {
     tag: String,
     value: String,
     units: Option<String>,
+    harmonized_name: Option<String>,
+    /// 1-based order among the sample's attributes as submitted
+    position: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 struct PossibleDate {
     tag: String,
     value: DateTime<Utc>,
+    precision: &'static str,
     tag_ok: bool,
 }
 
-type MyResult<T> = Result<T, Box<Error>>;
+/// An identifier or cross-reference, e.g., ("BioSample", "SAMEA2623861")
+/// or ("ENA-RUN", "ERR868459")
+#[derive(Debug, Clone, PartialEq)]
+struct Xref {
+    db: String,
+    id: String,
+}
+
+/// A low-confidence value decoded from a label, and where it came from
+#[derive(Debug)]
+struct Inferred {
+    field: String,
+    value: String,
+    source: String,
+}
+
+#[derive(Debug)]
+struct Warning {
+    field: String,
+    message: String,
+    /// Position of the attribute the warning is about, if any
+    position: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Sample {
+    accession: String,
+    biosample: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    taxon_id: Option<String>,
+    scientific_name: Option<String>,
+    common_name: Option<String>,
+    lineage: Vec<taxonomy::Taxon>,
+    identifiers: Vec<Xref>,
+    links: Vec<Xref>,
+    runs: Vec<String>,
+    experiments: Vec<String>,
+    studies: Vec<String>,
+    attributes: Vec<Attr>,
+    ena_attributes: Vec<Attr>,
+    ena: ena::EnaInfo,
+    dates: Vec<PossibleDate>,
+    depth: Option<f64>,
+    depth_range: Option<(f64, f64)>,
+    lat_lon: Option<(f64, f64)>,
+    env_terms: Vec<envo::EnvTerm>,
+    quantities: Vec<quantity::Quantity>,
+    size_fraction: Option<fraction::SizeFraction>,
+    run_info: Vec<experiment::RunInfo>,
+    study_info: Vec<study::Study>,
+    inferred: Vec<Inferred>,
+    warnings: Vec<Warning>,
+}
+
+impl Sample {
+    /// The first date found under a collection/event date tag
+    fn collection_date(&self) -> Option<&PossibleDate> {
+        self.dates.iter().find(|d| d.tag_ok)
+    }
+}
+
+impl PossibleDate {
+    /// The date only as precise as it was given, e.g., "2012-03"
+    fn to_precise_string(&self) -> String {
+        let format = description::PRECISIONS
+            .iter()
+            .find(|(precision, _)| *precision == self.precision)
+            .map_or("%Y-%m-%dT%H:%M:%S", |(_, format)| format);
+        self.value.format(format).to_string()
+    }
+}
+
+type MyResult<T> = Result<T, Box<dyn Error>>;
+
+/// Bump whenever a parser change can alter the output for the same input
+const RULESET_VERSION: &str = "18";
 //type Latitude<f64> = Latitude<f64>;
 
 // --------------------------------------------------
 // Public
 // --------------------------------------------------
 pub fn run(config: Config) -> MyResult<()> {
-    let files = find_files(&config.input)?;
-    println!(
-        "Will process {} file{}",
-        files.len(),
-        if files.len() == 1 { "" } else { "s" }
-    );
-
-    for (i, file) in files.iter().enumerate() {
-        println!("{}: {}", i + 1, file);
-        let f = File::open(file)?;
-        let root = Element::parse(f)?;
-
-        if let Err(e) = parse_xml(root) {
-            eprintln!("Error: {}", e);
+    match &config.command {
+        Command::VerifyReads(verify_config) => {
+            let prov = provenance::get_provenance(&input_paths(&config))?;
+            let mut samples = vec![];
+            process_samples(&config, &mut |sample| {
+                samples.push((sample.accession, sample.runs));
+                Ok(())
+            })?;
+            for line in prov.comment_lines() {
+                println!("{}", line);
+            }
+            verify::run_verify(verify_config, &samples)
+        }
+        Command::Export(export_config) => export_samples(&config, export_config),
+        Command::Validate(validate_config) => {
+            let checklists = checklist::read_checklists(&validate_config.checklists)?;
+            let prov = provenance::get_provenance(&input_paths(&config))?;
+            let mut reports = vec![];
+            process_samples(&config, &mut |sample| {
+                reports.push(checklist::validate(
+                    &sample,
+                    &checklists,
+                    validate_config.default_checklist.as_deref(),
+                ));
+                Ok(())
+            })?;
+            for line in prov.comment_lines() {
+                println!("{}", line);
+            }
+            checklist::print_reports(&reports);
+
+            let num_bad = reports.iter().filter(|r| !r.is_compliant()).count();
+            eprintln!(
+                "{} of {} samples compliant",
+                reports.len() - num_bad,
+                reports.len()
+            );
+            if num_bad > 0 {
+                return Err(From::from(format!(
+                    "{} sample{} not compliant",
+                    num_bad,
+                    if num_bad == 1 { "" } else { "s" }
+                )));
+            }
+            Ok(())
         }
-        break;
+        Command::Extract => export_samples(&config, &export::default_config()?),
     }
+}
 
-    Ok(())
+// --------------------------------------------------
+/// Writes every sample with the export settings, after the provenance
+fn export_samples(config: &Config, export_config: &export::ExportConfig) -> MyResult<()> {
+    let prov = provenance::get_provenance(&input_paths(config))?;
+    let mut writer = export::open_writer(export_config, &prov)?;
+    process_samples(config, &mut |sample| writer.write(&sample))?;
+    writer.finish()
 }
 
 // --------------------------------------------------
@@ -68,19 +237,228 @@ pub fn get_args() -> MyResult<Config> {
         .author("Ken Youens-Clark <kyclark@email.arizona.edu>")
         .about("Extract metadata from ENA XML")
         //.arg(
-            //Arg::with_name("xml")
-                //.short("x")
-                //.long("xml")
-                //.value_name("XML_FILE")
-                //.help("XML filename")
-                //.required(true),
+        //Arg::with_name("xml")
+        //.short("x")
+        //.long("xml")
+        //.value_name("XML_FILE")
+        //.help("XML filename")
+        //.required(true),
         //)
-        .arg(Arg::with_name("input").value_name("file.xml").multiple(true))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(
+            Arg::with_name("input")
+                .value_name("file.xml")
+                .multiple(true)
+                .required(true),
+        )
+        .args(&sample_args())
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Write the extracted samples as a table")
+                .arg(
+                    Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .value_name("FILE")
+                        .required_ifs(&[
+                            ("format", "parquet"),
+                            ("format", "dwca"),
+                            ("format", "xlsx"),
+                        ])
+                        .help("Output file, or directory with --partition-by (default STDOUT)"),
+                )
+                .arg(
+                    Arg::with_name("partition_by")
+                        .long("partition-by")
+                        .value_name("KEY")
+                        .possible_values(&["study", "taxon", "size_fraction"])
+                        .requires("out")
+                        .help("Write one file per ENA-STUDY, TAXON_ID or size fraction"),
+                )
+                .arg(
+                    Arg::with_name("primary_study")
+                        .long("primary-study")
+                        .requires("partition_by")
+                        .help("Write samples only to their first study's file"),
+                )
+                .arg(
+                    Arg::with_name("long")
+                        .long("long")
+                        .help("One row per sample attribute instead of per sample"),
+                )
+                .arg(
+                    Arg::with_name("wide")
+                        .long("wide")
+                        .conflicts_with("long")
+                        .help("One column per attribute tag, most common first"),
+                )
+                .arg(
+                    Arg::with_name("min_coverage")
+                        .long("min-coverage")
+                        .value_name("FRACTION")
+                        .requires("wide")
+                        .help("Drop --wide tags on fewer than this fraction of samples"),
+                )
+                .arg(
+                    Arg::with_name("include_ena")
+                        .long("include-ena")
+                        .help("Include ENA-* attributes in --long or --wide output"),
+                )
+                .arg(
+                    Arg::with_name("sqlite")
+                        .long("sqlite")
+                        .value_name("DB")
+                        .conflicts_with("out")
+                        .help("Load into a SQLite database instead of a table"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["tsv", "csv", "parquet", "geojson", "dwca", "xlsx"])
+                        .default_value("tsv")
+                        .help("Output format"),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .short("c")
+                        .long("columns")
+                        .value_name("COLUMNS")
+                        .default_value(export::DEFAULT_COLUMNS)
+                        .help("Comma-separated fields and attr:<TAG> columns"),
+                )
+                .arg(
+                    Arg::with_name("delimiter")
+                        .short("d")
+                        .long("delimiter")
+                        .value_name("DELIMITER")
+                        .help("Field delimiter (default tab for tsv, comma for csv)"),
+                )
+                .arg(
+                    Arg::with_name("quote")
+                        .long("quote")
+                        .value_name("QUOTE")
+                        .possible_values(&["necessary", "always", "never", "non-numeric"])
+                        .default_value("necessary")
+                        .help("When to quote values"),
+                )
+                .arg(
+                    Arg::with_name("na")
+                        .long("na")
+                        .value_name("TOKEN")
+                        .default_value("")
+                        .help("Value written for missing fields"),
+                )
+                .args(&sample_args())
+                .arg(
+                    Arg::with_name("input")
+                        .value_name("file.xml")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Check samples against their ENA checklists")
+                .arg(
+                    Arg::with_name("checklists")
+                        .short("c")
+                        .long("checklists")
+                        .value_name("CHECKLIST_XML")
+                        .help("ENA CHECKLIST_SET XML file(s) or directory")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("default_checklist")
+                        .long("default-checklist")
+                        .value_name("ACCESSION")
+                        .help("Checklist for samples without ENA-CHECKLIST, e.g., ERC000011"),
+                )
+                .args(&sample_args())
+                .arg(
+                    Arg::with_name("input")
+                        .value_name("file.xml")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify-reads")
+                .about("Check downloaded FASTQ files against sample runs")
+                .arg(
+                    Arg::with_name("dir")
+                        .short("d")
+                        .long("dir")
+                        .value_name("DIR")
+                        .help("Directory of ERR*.fastq.gz files")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("filereport")
+                        .short("f")
+                        .long("filereport")
+                        .value_name("FILEREPORT")
+                        .help("ENA filereport TSV with fastq_md5/fastq_bytes"),
+                )
+                .args(&sample_args())
+                .arg(
+                    Arg::with_name("input")
+                        .value_name("file.xml")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
         .get_matches();
 
+    let (opts, command) = match matches.subcommand() {
+        ("verify-reads", Some(sub)) => (
+            sub,
+            Command::VerifyReads(verify::VerifyConfig {
+                read_dir: sub.value_of("dir").unwrap().to_string(),
+                filereport: sub.value_of("filereport").map(|s| s.to_string()),
+            }),
+        ),
+        ("export", Some(sub)) => (sub, Command::Export(export::get_config(sub)?)),
+        ("validate", Some(sub)) => (
+            sub,
+            Command::Validate(checklist::ValidateConfig {
+                checklists: sub.values_of_lossy("checklists").unwrap_or_default(),
+                default_checklist: sub.value_of("default_checklist").map(|s| s.to_string()),
+            }),
+        ),
+        _ => (&matches, Command::Extract),
+    };
+
     let config = Config {
         //xml_file: matches.value_of("xml").unwrap().to_string(),
-        input: matches.values_of_lossy("input").unwrap(),
+        input: opts.values_of_lossy("input").unwrap(),
+        command,
+        experiments: opts.values_of_lossy("experiments").unwrap_or_default(),
+        runs: opts.values_of_lossy("runs").unwrap_or_default(),
+        run_filter: experiment::RunFilter {
+            library_strategy: opts.value_of("library_strategy").map(|s| s.to_string()),
+            library_source: opts.value_of("library_source").map(|s| s.to_string()),
+            platform: opts.value_of("platform").map(|s| s.to_string()),
+        },
+        ena_filter: ena::EnaFilter {
+            min_spots: ena::parse_count_arg(opts.value_of("min_spots"))?,
+            min_bases: ena::parse_count_arg(opts.value_of("min_bases"))?,
+            public_after: ena::parse_date_arg(opts.value_of("public_after"))?,
+            public_before: ena::parse_date_arg(opts.value_of("public_before"))?,
+        },
+        studies: opts.values_of_lossy("studies").unwrap_or_default(),
+        taxon_filter: taxon::TaxonFilter {
+            taxa: opts.values_of_lossy("taxa").unwrap_or_default(),
+            descendants: opts.is_present("taxon_descendants"),
+        },
+        taxonomy: opts.value_of("taxonomy").map(|s| s.to_string()),
+        taxonomy_cache: opts.value_of("taxonomy_cache").map(|s| s.to_string()),
+        envo: opts.value_of("envo").map(|s| s.to_string()),
+        decoders: decode::get_decoders(&opts.values_of_lossy("decoders").unwrap_or_default())?,
+        units: quantity::get_targets(&opts.values_of_lossy("units").unwrap_or_default())?,
     };
 
     Ok(config)
@@ -89,7 +467,188 @@ pub fn get_args() -> MyResult<Config> {
 // --------------------------------------------------
 // Private
 // --------------------------------------------------
-fn find_files(paths: &Vec<String>) -> Result<Vec<String>, Box<Error>> {
+/// Options for enriching and filtering samples, shared by the subcommands
+fn sample_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("experiments")
+            .short("e")
+            .long("experiments")
+            .value_name("EXPERIMENT_XML")
+            .help("EXPERIMENT_SET XML file(s) or directory")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("runs")
+            .short("r")
+            .long("runs")
+            .value_name("RUN_XML")
+            .help("RUN_SET XML file(s) or directory")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("studies")
+            .short("s")
+            .long("studies")
+            .value_name("STUDY_XML")
+            .help("STUDY_SET/PROJECT_SET XML file(s) or directory")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("library_strategy")
+            .long("library-strategy")
+            .value_name("STRATEGY")
+            .help("Keep runs with this library strategy, e.g., WGS"),
+        Arg::with_name("library_source")
+            .long("library-source")
+            .value_name("SOURCE")
+            .help("Keep runs with this library source, e.g., METAGENOMIC"),
+        Arg::with_name("platform")
+            .long("platform")
+            .value_name("PLATFORM")
+            .help("Keep runs from this platform, e.g., ILLUMINA"),
+        Arg::with_name("min_spots")
+            .long("min-spots")
+            .value_name("SPOTS")
+            .help("Keep samples with at least this ENA-SPOT-COUNT"),
+        Arg::with_name("min_bases")
+            .long("min-bases")
+            .value_name("BASES")
+            .help("Keep samples with at least this ENA-BASE-COUNT"),
+        Arg::with_name("public_after")
+            .long("public-after")
+            .value_name("YYYY-MM-DD")
+            .help("Keep samples first public on or after this date"),
+        Arg::with_name("public_before")
+            .long("public-before")
+            .value_name("YYYY-MM-DD")
+            .help("Keep samples first public on or before this date"),
+        Arg::with_name("taxa")
+            .long("taxon")
+            .value_name("TAXON")
+            .help("Keep samples of this taxon ID or name, e.g., 408172 or \"soil metagenome\"")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("taxon_descendants")
+            .long("taxon-descendants")
+            .help("Let --taxon also keep samples of its descendant taxa")
+            .requires_all(&["taxa", "taxonomy"]),
+        Arg::with_name("taxonomy")
+            .long("taxonomy")
+            .value_name("TAXDUMP_DIR")
+            .help("Directory with NCBI names.dmp/nodes.dmp to resolve lineages"),
+        Arg::with_name("taxonomy_cache")
+            .long("taxonomy-cache")
+            .value_name("FILE")
+            .help("Taxonomy cache file (default TAXDUMP_DIR/taxonomy.mextract.bin)")
+            .requires("taxonomy"),
+        Arg::with_name("envo")
+            .long("envo")
+            .value_name("ENVO_FILE")
+            .help("ENVO OBO/OWL file to validate environment terms against"),
+        Arg::with_name("units")
+            .long("unit")
+            .value_name("FIELD=UNIT")
+            .help("Convert a measurement to this unit, e.g., nitrate=umol/kg")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("decoders")
+            .long("decoder")
+            .value_name("NAME=REGEX")
+            .help("Decode TITLE/Event Label with named groups, e.g., (?P<depth>...)")
+            .multiple(true)
+            .number_of_values(1),
+    ]
+}
+
+// --------------------------------------------------
+/// Every file that can change the output, for provenance
+fn input_paths(config: &Config) -> Vec<String> {
+    let mut paths: Vec<String> = [
+        &config.input,
+        &config.experiments,
+        &config.runs,
+        &config.studies,
+    ]
+    .iter()
+    .flat_map(|paths| paths.iter().cloned())
+    .collect();
+
+    if let Some(dir) = &config.taxonomy {
+        paths.extend(taxonomy::source_files(
+            dir,
+            config.taxonomy_cache.as_deref(),
+        ));
+    }
+    paths.extend(config.envo.iter().cloned());
+
+    match &config.command {
+        Command::VerifyReads(verify_config) => {
+            paths.extend(verify_config.filereport.iter().cloned())
+        }
+        Command::Validate(validate_config) => {
+            paths.extend(validate_config.checklists.iter().cloned())
+        }
+        _ => (),
+    }
+
+    paths
+}
+
+// --------------------------------------------------
+/// Reads, enriches and filters every sample in the input files, handing
+/// each to `handle` as it goes
+fn process_samples(
+    config: &Config,
+    handle: &mut dyn FnMut(Sample) -> MyResult<()>,
+) -> MyResult<()> {
+    let files = find_files(&config.input)?;
+    let experiments = experiment::read_experiments(&config.experiments)?;
+    let runs = experiment::read_runs(&config.runs)?;
+    let studies = study::read_studies(&config.studies)?;
+    let taxonomy = match &config.taxonomy {
+        Some(dir) => Some(taxonomy::load(dir, config.taxonomy_cache.as_deref())?),
+        _ => None,
+    };
+    let envo = match &config.envo {
+        Some(path) => Some(envo::load(path)?),
+        _ => None,
+    };
+    eprintln!(
+        "Will process {} file{}",
+        files.len(),
+        if files.len() == 1 { "" } else { "s" }
+    );
+
+    for (i, file) in files.iter().enumerate() {
+        eprintln!("{}: {}", i + 1, file);
+
+        for record in read_samples(file, &config.decoders)? {
+            match record {
+                Ok(mut sample) => {
+                    if let Some(taxonomy) = &taxonomy {
+                        taxonomy.resolve(&mut sample);
+                    }
+                    if !config.taxon_filter.accepts(&sample) || !config.ena_filter.accepts(&sample)
+                    {
+                        continue;
+                    }
+                    if let Some(envo) = &envo {
+                        envo.validate(&mut sample);
+                    }
+                    quantity::normalize(&mut sample, &config.units);
+                    experiment::join_runs(&mut sample, &experiments, &runs);
+                    study::join_studies(&mut sample, &studies);
+                    if experiment::filter_runs(&mut sample, &config.run_filter) {
+                        handle(sample)?;
+                    }
+                }
+                Err(e) => eprintln!("Error: {}: {}", file, e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+fn find_files(paths: &[String]) -> MyResult<Vec<String>> {
     let mut files = vec![];
     for path in paths {
         let meta = fs::metadata(path)?;
@@ -106,7 +665,7 @@ fn find_files(paths: &Vec<String>) -> Result<Vec<String>, Box<Error>> {
         };
     }
 
-    if files.len() == 0 {
+    if files.is_empty() {
         return Err(From::from("No input files"));
     }
 
@@ -114,27 +673,151 @@ fn find_files(paths: &Vec<String>) -> Result<Vec<String>, Box<Error>> {
 }
 
 // --------------------------------------------------
-fn parse_xml(root: Element) -> MyResult<()> {
-    let id = get_primary_id(&root)?;
-    println!("id {:?}", id);
+/// Reads every sample in an ENA SAMPLE/SAMPLE_SET or NCBI
+/// BioSample/BioSampleSet file, detected from the root element, or in an
+/// SRA run table
+fn read_samples(
+    file: &str,
+    decoders: &[Box<dyn decode::LabelDecoder>],
+) -> MyResult<Vec<MyResult<Sample>>> {
+    if !is_xml(file)? {
+        return sra::read_run_table(file, decoders);
+    }
+
+    let root = Element::parse(File::open(file)?)?;
+
+    let samples = match root.name.as_str() {
+        "SAMPLE" => vec![parse_xml(root, decoders)],
+        "SAMPLE_SET" => root
+            .children
+            .into_iter()
+            .map(|child| parse_xml(child, decoders))
+            .collect(),
+        "BioSample" => vec![biosample::parse_biosample(&root, decoders)],
+        "BioSampleSet" => root
+            .children
+            .iter()
+            .map(|child| biosample::parse_biosample(child, decoders))
+            .collect(),
+        name => {
+            return Err(From::from(format!(
+                "Unknown sample format (root element \"{}\")",
+                name
+            )))
+        }
+    };
+
+    Ok(samples)
+}
 
+// --------------------------------------------------
+fn is_xml(file: &str) -> MyResult<bool> {
+    let mut reader = BufReader::new(File::open(file)?);
+    let buf = reader.fill_buf()?;
+    let start = buf.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(buf);
+
+    Ok(start
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| *b == b'<'))
+}
+
+// --------------------------------------------------
+fn parse_xml(root: Element, decoders: &[Box<dyn decode::LabelDecoder>]) -> MyResult<Sample> {
+    let id = get_primary_id(&root)?;
     let runs = get_runs(&root);
-    println!("runs {:?}", runs);
 
-    let skip_re = Regex::new(r"^ENA-").unwrap();
-    let attrs = get_attributes(&root, Some(skip_re))?;
-    println!("attr {:?}", attrs);
+    // ENA's own ENA-* attributes are kept apart from the submitter's
+    let ena_re = Regex::new(r"^ENA-").unwrap();
+    let (ena_attrs, attrs): (Vec<Attr>, Vec<Attr>) = get_attributes(&root, None)?
+        .into_iter()
+        .partition(|attr| ena_re.is_match(&attr.tag));
+
+    let biosample = root.get_child("IDENTIFIERS").and_then(|ids| {
+        ids.children
+            .iter()
+            .find(|id| {
+                id.name == "EXTERNAL_ID"
+                    && id.attributes.get("namespace").map(|n| n.as_str()) == Some("BioSample")
+            })
+            .and_then(|id| id.text.clone())
+    });
+
+    let name = root.get_child("SAMPLE_NAME");
+    let mut sample = Sample {
+        accession: id,
+        biosample,
+        title: get_child_text(&root, "TITLE"),
+        description: get_child_text(&root, "DESCRIPTION"),
+        taxon_id: name.and_then(|name| get_child_text(name, "TAXON_ID")),
+        scientific_name: name.and_then(|name| get_child_text(name, "SCIENTIFIC_NAME")),
+        common_name: name.and_then(|name| get_child_text(name, "COMMON_NAME")),
+        identifiers: get_identifiers(&root),
+        links: get_xrefs(&root),
+        runs: runs.unwrap_or_default(),
+        experiments: get_links(&root, "ENA-EXPERIMENT"),
+        studies: get_links(&root, "ENA-STUDY"),
+        attributes: attrs,
+        ena_attributes: ena_attrs,
+        ..Default::default()
+    };
+    parse_fields(&mut sample, decoders);
+
+    Ok(sample)
+}
+
+// --------------------------------------------------
+/// Normalizes dates, depth, coordinates, environment terms, measurements
+/// and size fraction from the sample's attributes, decoding labels for
+/// what they lack before the size fraction and DESCRIPTION are read
+fn parse_fields(sample: &mut Sample, decoders: &[Box<dyn decode::LabelDecoder>]) {
+    sample.dates = get_dates(&sample.attributes).unwrap_or_default();
+    sample.depth = get_depth(&sample.attributes);
+    sample.depth_range = get_depth_range(&sample.attributes);
+    sample.lat_lon = get_lat_lon(&sample.attributes);
+    sample.env_terms = envo::get_env_terms(&sample.attributes);
+    let (quantities, quantity_warnings) = quantity::get_quantities(&sample.attributes);
+    sample.quantities = quantities;
+    sample.warnings = get_parse_warnings(sample);
+    sample.warnings.extend(envo::get_env_warnings(sample));
+    sample.warnings.extend(quantity_warnings);
+    decode::apply_decoders(sample, decoders);
+    let (size_fraction, size_warnings) = fraction::get_size_fraction(sample);
+    sample.size_fraction = size_fraction;
+    sample.warnings.extend(size_warnings);
+    ena::parse_ena(sample);
+    description::mine_description(sample);
+}
+
+// --------------------------------------------------
+/// NCBI's harmonized attribute names are the strongest hint of meaning
+fn is_harmonized(attr: &Attr, name: &str) -> bool {
+    attr.harmonized_name.as_deref() == Some(name)
+}
 
-    let dates = get_dates(&attrs);
-    println!("dates {:?}", dates);
+// --------------------------------------------------
+/// Parses each XML file, which may hold a single record or a *_SET
+fn read_sets(paths: &[String]) -> MyResult<Vec<Element>> {
+    if paths.is_empty() {
+        return Ok(vec![]);
+    }
 
-    let depth = get_depth(&attrs);
-    println!("depth {:?}", depth);
+    let mut roots = vec![];
+    for file in find_files(paths)? {
+        roots.push(Element::parse(File::open(&file)?)?);
+    }
 
-    let lat_lon = get_lat_lon(&attrs);
-    println!("lat_lon {:?}", lat_lon);
+    Ok(roots)
+}
 
-    Ok(())
+// --------------------------------------------------
+/// Accepts either a single element or a *_SET of them
+fn set_members<'a>(root: &'a Element, name: &str) -> Vec<&'a Element> {
+    if root.name == name {
+        vec![root]
+    } else {
+        root.children.iter().filter(|c| c.name == name).collect()
+    }
 }
 
 // --------------------------------------------------
@@ -159,18 +842,34 @@ fn get_primary_id(root: &Element) -> MyResult<String> {
 
 // --------------------------------------------------
 fn get_runs(root: &Element) -> Option<Vec<String>> {
-    let mut runs: Vec<String> = vec![];
+    Some(get_links(root, "ENA-RUN"))
+}
+
+// --------------------------------------------------
+fn get_links(root: &Element, db: &str) -> Vec<String> {
+    get_xrefs(root)
+        .into_iter()
+        .filter(|xref| xref.db == db)
+        .map(|xref| xref.id)
+        .collect()
+}
+
+// --------------------------------------------------
+/// Every SAMPLE_LINKS XREF_LINK, one per (expanded) ID
+fn get_xrefs(root: &Element) -> Vec<Xref> {
+    let mut xrefs: Vec<Xref> = vec![];
     if let Some(links) = root.get_child("SAMPLE_LINKS") {
         for link in links.children.iter() {
             if let Some(xref) = link.get_child("XREF_LINK") {
-                if let Some(run) = xref.get_child("DB") {
-                    if run.text == Some("ENA-RUN".to_string()) {
-                        if let Some(id) = xref.get_child("ID") {
-                            if let Some(s) = id.text.as_ref() {
-                                for t in s.split(",") {
-                                    runs.push(t.to_string());
-                                }
-                            }
+                if let (Some(db), Some(ids)) =
+                    (get_child_text(xref, "DB"), get_child_text(xref, "ID"))
+                {
+                    for t in ids.split(",") {
+                        for id in expand_id_range(t.trim()) {
+                            xrefs.push(Xref {
+                                db: db.to_string(),
+                                id,
+                            });
                         }
                     }
                 }
@@ -178,17 +877,59 @@ fn get_runs(root: &Element) -> Option<Vec<String>> {
         }
     }
 
-    Some(runs)
+    xrefs
+}
+
+// --------------------------------------------------
+/// The IDENTIFIERS, named by namespace when there is one (e.g.,
+/// "BioSample") or else by the element (e.g., "PRIMARY_ID")
+fn get_identifiers(root: &Element) -> Vec<Xref> {
+    root.get_child("IDENTIFIERS")
+        .map(|ids| {
+            ids.children
+                .iter()
+                .filter_map(|id| {
+                    id.text.as_ref().map(|text| Xref {
+                        db: id
+                            .attributes
+                            .get("namespace")
+                            .cloned()
+                            .unwrap_or_else(|| id.name.to_string()),
+                        id: text.trim().to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// --------------------------------------------------
+/// ENA abbreviates consecutive accessions, e.g., "ERP006156-ERP006157"
+fn expand_id_range(val: &str) -> Vec<String> {
+    let re = Regex::new(r"^([A-Z]+)(\d+)-([A-Z]+)(\d+)$").unwrap();
+    if let Some(caps) = re.captures(val) {
+        let width = caps[2].len();
+        if let (Ok(start), Ok(end)) = (caps[2].parse::<u64>(), caps[4].parse::<u64>()) {
+            if caps[1] == caps[3] && start <= end {
+                return (start..=end)
+                    .map(|n| format!("{}{:0width$}", &caps[1], n, width = width))
+                    .collect();
+            }
+        }
+    }
+
+    vec![val.to_string()]
 }
 
 // --------------------------------------------------
 fn get_child_text(element: &Element, tag: &str) -> Option<String> {
-    element.get_child(tag).and_then(|child| {
-        child.text.as_ref().and_then(|val| Some(val.to_string()))
-    })
+    element
+        .get_child(tag)
+        .and_then(|child| child.text.as_ref().map(|val| val.to_string()))
 }
 
 // --------------------------------------------------
+/// Every tagged SAMPLE_ATTRIBUTE, including those without a VALUE
 fn get_attributes(root: &Element, skip: Option<Regex>) -> MyResult<Vec<Attr>> {
     let skip_tag = |tag: &str| match &skip {
         Some(re) => re.is_match(tag),
@@ -198,22 +939,21 @@ fn get_attributes(root: &Element, skip: Option<Regex>) -> MyResult<Vec<Attr>> {
     match root.get_child("SAMPLE_ATTRIBUTES") {
         Some(attributes) => {
             let mut attrs: Vec<Attr> = vec![];
-            for attr in attributes.children.iter() {
-                if let Some(tag) = get_child_text(&attr, "TAG") {
+            for (i, attr) in attributes.children.iter().enumerate() {
+                if let Some(tag) = get_child_text(attr, "TAG") {
                     if skip_tag(&tag) {
                         continue;
                     }
 
-                    if let Some(value) = get_child_text(&attr, "VALUE") {
-                        attrs.push(Attr {
-                            tag: tag,
-                            value: value,
-                            units: get_child_text(attr, "UNITS"),
-                        });
-                    }
+                    attrs.push(Attr {
+                        tag,
+                        value: get_child_text(attr, "VALUE").unwrap_or_default(),
+                        units: get_child_text(attr, "UNITS"),
+                        harmonized_name: None,
+                        position: i + 1,
+                    });
                 }
             }
-            println!("attr = {:?}", attrs);
             Ok(attrs)
         }
         _ => Err(From::from("Missing SAMPLE_ATTRIBUTES")),
@@ -221,88 +961,109 @@ fn get_attributes(root: &Element, skip: Option<Regex>) -> MyResult<Vec<Attr>> {
 }
 
 // --------------------------------------------------
-fn get_depth(attrs: &Vec<Attr>) -> Option<f64> {
-    let tag_re = Regex::new(
-        r"(?i)^(?:geographic(?:al)? location [(])?depth[)]?",
-    ).unwrap();
+fn get_depth(attrs: &[Attr]) -> Option<f64> {
+    depth_attr(attrs).and_then(|attr| parse_depth(&attr.value))
+}
 
-    for attr in attrs.iter() {
-        if tag_re.is_match(&attr.tag) {
-            return parse_depth(&attr.value);
-        }
-    }
+// --------------------------------------------------
+/// Minimum and maximum depth in meters, e.g., (3., 7.) for "3-7 m" or
+/// (5., 5.) for a single depth
+fn get_depth_range(attrs: &[Attr]) -> Option<(f64, f64)> {
+    depth_attr(attrs).and_then(|attr| parse_depth_range(&attr.value))
+}
 
-    None
+// --------------------------------------------------
+fn depth_attr(attrs: &[Attr]) -> Option<&Attr> {
+    let tag_re = Regex::new(r"(?i)^(?:geographic(?:al)? location [(])?depth[)]?").unwrap();
+
+    attrs
+        .iter()
+        .find(|a| is_harmonized(a, "depth"))
+        .or_else(|| attrs.iter().find(|a| tag_re.is_match(&a.tag)))
 }
 
 // --------------------------------------------------
-fn parse_depth(val: &str) -> Option<f64> {
-    println!("VAL = {}", val);
-    let patterns = vec![
-        // 5, 5., 5.0
-        r"(?x)
+fn parse_depth_range(val: &str) -> Option<(f64, f64)> {
+    let range_re = Regex::new(
+        r"(?xi)
         ^
-        (?P<num>\d+(?:\.(?:\d+)?)?)
         \s*
-        (?P<unit>\w+)?
-        $
-        ",
-        // .5, 0.5
-        r"(?x)
-        ^
-        (?P<num>(?:\d+)?\.\d+)
+        (?P<min>\d*\.?\d+)
+        \s*
+        (?:-|to)
+        \s*
+        (?P<max>\d*\.?\d+)
         \s*
         (?P<unit>\w+)?
         $
         ",
-    ];
+    )
+    .unwrap();
+
+    match range_re.captures(val) {
+        Some(caps) => {
+            let unit = caps.name("unit").map_or("", |u| u.as_str());
+            let min = parse_depth(&format!("{}{}", &caps["min"], unit))?;
+            let max = parse_depth(&format!("{}{}", &caps["max"], unit))?;
+            Some((min.min(max), min.max(max)))
+        }
+        _ => parse_depth(val).map(|depth| (depth, depth)),
+    }
+}
 
-    for pattern in patterns {
-        let re = Regex::new(&pattern).unwrap();
-        if let Some(caps) = re.captures(&val) {
-            let mult = match caps.name("unit") {
-                Some(unit_val) => {
-                    let unit_pat = r"(?ix)
-                        ^
-                        (?P<prefix>c(?:enti)?|m(?:illi)?)?
-                        m
-                        (?:eters?)?
-                        $
-                        ";
-                    let unit_re = Regex::new(&unit_pat).unwrap();
-
-                    if let Some(c) = unit_re.captures(&unit_val.as_str()) {
-                        if let Some(m) = c.name("prefix") {
-                            match m.as_str() {
-                                "c" => 0.01,
-                                "centi" => 0.01,
-                                "m" => 0.001,
-                                "milli" => 0.001,
-                                _ => 1.,
-                            }
-                        } else {
-                            1.
-                        }
-                    } else {
-                        1.
-                    }
-                }
-                _ => 1.,
-            };
+// --------------------------------------------------
+fn parse_depth(val: &str) -> Option<f64> {
+    // 5, 5., 5.0, .5, 0.5 with an optional unit word; unknown units are
+    // taken as meters
+    let (num, unit) = quantity::split_number(val)
+        .filter(|_| val.starts_with(|c: char| c.is_ascii_digit() || c == '.'))?;
+
+    if unit.is_empty() {
+        Some(num)
+    } else if unit.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        quantity::convert(num, unit, "m").or(Some(num))
+    } else {
+        None
+    }
+}
 
-            if let Some(num) = caps.name("num") {
-                if let Ok(n) = num.as_str().parse::<f64>() {
-                    return Some(n * mult);
-                }
-            }
+// --------------------------------------------------
+fn get_dates(attrs: &[Attr]) -> Option<Vec<PossibleDate>> {
+    let tag_re = date_tag_re();
+
+    let mut dates: Vec<PossibleDate> = vec![];
+    for attr in attrs.iter() {
+        let val = &attr.value;
+        //println!("\n\n{} = {}", attr.tag, val);
+
+        if let Some((dt, precision)) = parse_datetime(val) {
+            //println!("DATE => {:?}", dt);
+            dates.push(PossibleDate {
+                tag: attr.tag.to_string(),
+                value: dt,
+                precision,
+                tag_ok: is_date_tag(attr, &tag_re),
+            });
         }
     }
 
-    None
+    //if dates.len() > 0 {
+    //    let num_ok = &dates.iter().filter(|d| d.tag_ok).count() as u32;
+    //    if num_ok == 1 {
+    //        Some(dates.iter().filter(|d| d.tag_ok).collect())
+    //    } else {
+    //        Some(dates)
+    //    }
+    //} else {
+    //    None
+    //}
+
+    Some(dates)
 }
 
 // --------------------------------------------------
-fn get_dates(attrs: &Vec<Attr>) -> Option<Vec<PossibleDate>> {
+/// Tags for the collection date (or event start)
+fn date_tag_re() -> RegexSet {
     let tag_patterns = [
         r"(?xi)
         ^
@@ -336,39 +1097,48 @@ fn get_dates(attrs: &Vec<Attr>) -> Option<Vec<PossibleDate>> {
     ];
 
     // cf https://docs.rs/chrono/0.4.0/chrono/format/strftime/index.html
-    let tag_re = RegexSet::new(&tag_patterns).unwrap();
+    RegexSet::new(tag_patterns).unwrap()
+}
 
-    let mut dates: Vec<PossibleDate> = vec![];
-    for attr in attrs.iter() {
-        let val = &attr.value;
-        //println!("\n\n{} = {}", attr.tag, val);
+// --------------------------------------------------
+fn is_date_tag(attr: &Attr, tag_re: &RegexSet) -> bool {
+    tag_re.is_match(&attr.tag) || is_harmonized(attr, "collection_date")
+}
 
-        if let Some(dt) = parse_datetime(&val) {
-            //println!("DATE => {:?}", dt);
-            dates.push(PossibleDate {
-                tag: attr.tag.to_string(),
-                value: dt,
-                tag_ok: tag_re.is_match(&attr.tag),
-            });
+// --------------------------------------------------
+fn get_lat_lon(attrs: &[Attr]) -> Option<(f64, f64)> {
+    let classes = lat_lon_tag_classes();
+
+    if let Some(attr) = attrs.iter().find(|a| is_harmonized(a, "lat_lon")) {
+        if let Some(lat_lon) = parse_lat_lon(&attr.value) {
+            return Some(lat_lon);
         }
     }
 
-    //if dates.len() > 0 {
-    //    let num_ok = &dates.iter().filter(|d| d.tag_ok).count() as u32;
-    //    if num_ok == 1 {
-    //        Some(dates.iter().filter(|d| d.tag_ok).collect())
-    //    } else {
-    //        Some(dates)
-    //    }
-    //} else {
-    //    None
-    //}
+    let (mut lat, mut lon) = (None, None);
+    for attr in attrs.iter() {
+        if let Some(class) = classify_lat_lon_tag(&classes, &attr.tag) {
+            match class {
+                "combined" => {
+                    if let Some(lat_lon) = parse_lat_lon(&attr.value) {
+                        return Some(lat_lon);
+                    }
+                }
+                "lat" => lat = lat.or_else(|| parse_coord(&attr.value, 90.)),
+                _ => lon = lon.or_else(|| parse_coord(&attr.value, 180.)),
+            }
+        }
+    }
 
-    Some(dates)
+    match (lat, lon) {
+        (Some(lat), Some(lon)) => Some((lat, lon)),
+        _ => None,
+    }
 }
 
 // --------------------------------------------------
-fn get_lat_lon(attrs: &Vec<Attr>) -> Option<&str> {
+/// Tag patterns for combined "lat lon" values and separate coordinates
+fn lat_lon_tag_classes() -> Vec<(&'static str, Vec<Regex>)> {
     let tag_patterns_combined = vec![
         r"(?xi)
         ^
@@ -419,37 +1189,177 @@ fn get_lat_lon(attrs: &Vec<Attr>) -> Option<&str> {
         ",
     ];
 
-    fn re_fct(v: &Vec<&str>) -> Vec<Regex> {
-        v.into_iter().map(|p| Regex::new(p).unwrap()).collect()
+    fn re_fct(v: &[&str]) -> Vec<Regex> {
+        v.iter().map(|p| Regex::new(p).unwrap()).collect()
     }
 
-    let mut pat_map: HashMap<String, Vec<Regex>> = HashMap::new();
-    pat_map.insert("combined".to_string(), re_fct(&tag_patterns_combined));
-    pat_map.insert("lat".to_string(), re_fct(&tag_patterns_lat));
-    pat_map.insert("lon".to_string(), re_fct(&tag_patterns_lon));
+    vec![
+        ("combined", re_fct(&tag_patterns_combined)),
+        ("lat", re_fct(&tag_patterns_lat)),
+        ("lon", re_fct(&tag_patterns_lon)),
+    ]
+}
 
-    let classify_tag = |tag: &str| {
-        for (class, tag_regexes) in pat_map.iter() {
-            for tag_re in tag_regexes {
-                if tag_re.is_match(&tag) {
-                    return Some(class);
-                }
-            }
-        }
-        None
+// --------------------------------------------------
+fn classify_lat_lon_tag(classes: &[(&'static str, Vec<Regex>)], tag: &str) -> Option<&'static str> {
+    classes
+        .iter()
+        .find(|(_, tag_regexes)| tag_regexes.iter().any(|re| re.is_match(tag)))
+        .map(|(class, _)| *class)
+}
+
+// --------------------------------------------------
+/// Flags the attributes that look like they hold the collection date,
+/// depth or coordinates when that field could not be parsed from them
+fn get_parse_warnings(sample: &Sample) -> Vec<Warning> {
+    let unparsed = |field: &str, attr: &Attr| Warning {
+        field: field.to_string(),
+        message: format!("could not parse \"{}\" = \"{}\"", attr.tag, attr.value),
+        position: Some(attr.position),
     };
+    let has_value = |attr: &&Attr| !attr.value.trim().is_empty();
+
+    let mut warnings = vec![];
+    if sample.collection_date().is_none() {
+        let tag_re = date_tag_re();
+        warnings.extend(
+            sample
+                .attributes
+                .iter()
+                .filter(has_value)
+                .filter(|a| is_date_tag(a, &tag_re))
+                .map(|a| unparsed("collection_date", a)),
+        );
+    }
 
-    for attr in attrs.iter() {
-        if let Some(class) = classify_tag(&attr.tag) {
-            println!("tag \"{}\" = {}", &attr.tag, &class);
+    if sample.depth.is_none() {
+        if let Some(attr) = depth_attr(&sample.attributes).filter(has_value) {
+            warnings.push(unparsed("depth", attr));
+        }
+    }
 
-            //let parser = match class {
+    if sample.lat_lon.is_none() {
+        let classes = lat_lon_tag_classes();
+        warnings.extend(
+            sample
+                .attributes
+                .iter()
+                .filter(has_value)
+                .filter(|a| {
+                    is_harmonized(a, "lat_lon") || classify_lat_lon_tag(&classes, &a.tag).is_some()
+                })
+                .map(|a| unparsed("lat_lon", a)),
+        );
+    }
 
-            //};
+    warnings
+}
+
+// --------------------------------------------------
+fn parse_lat_lon(val: &str) -> Option<(f64, f64)> {
+    // 36.1715 -29.023, 36.1715 N 29.023 W, lat 36.1715 long -29.023
+    let re = Regex::new(
+        r"(?xi)
+        ^
+        \s*
+        (?:lat:?\s*)?
+        (?P<lat>[+-]?\d+(?:\.\d+)?)
+        (?:\s*(?P<ns>[NS]))?
+        (?:_|\s+|\s*,\s*)
+        (?:long?:?\s*)?
+        (?P<lon>[+-]?\d+(?:\.\d+)?)
+        (?:\s*(?P<ew>[EW]))?
+        (?:,\s+decimal\s+degrees)?
+        \s*
+        $
+        ",
+    )
+    .unwrap();
+
+    let (lat, lon) = match re.captures(val) {
+        Some(caps) => {
+            let lat = signed_coord(&caps["lat"], caps.name("ns").map(|m| m.as_str()));
+            let lon = signed_coord(&caps["lon"], caps.name("ew").map(|m| m.as_str()));
+            (lat, lon)
         }
+        _ => match parse_lat_lon_combined(val) {
+            Some((lat, lon)) => (lat.parse().ok(), lon.parse().ok()),
+            _ => (None, None),
+        },
+    };
+
+    match (lat, lon) {
+        (Some(lat), Some(lon)) if lat.abs() <= 90. && lon.abs() <= 180. => Some((lat, lon)),
+        _ => None,
     }
+}
 
-    None
+// --------------------------------------------------
+fn signed_coord(num: &str, direction: Option<&str>) -> Option<f64> {
+    num.parse::<f64>().ok().map(|n| match direction {
+        Some(d) if d.eq_ignore_ascii_case("S") || d.eq_ignore_ascii_case("W") => -n.abs(),
+        _ => n,
+    })
+}
+
+// --------------------------------------------------
+/// A single latitude or longitude in decimal degrees or DMS
+fn parse_coord(val: &str, max: f64) -> Option<f64> {
+    let decimal_re = Regex::new(
+        r"(?xi)
+        ^
+        \s*
+        (?P<num>[+-]?\d+(?:\.\d+)?)
+        \s*
+        [º°]?
+        \s*
+        (?P<dir>[NSEW])?
+        \s*
+        $
+        ",
+    )
+    .unwrap();
+
+    let dms_re = Regex::new(
+        r#"(?xi)
+        ^
+        \s*
+        (?P<neg>-)?
+        (?P<deg>\d+)
+        \s*
+        [º°]
+        \s*
+        (?P<min>\d+(?:\.\d+)?)
+        \s*
+        ['’]
+        (?:
+          \s*
+          (?P<sec>\d+(?:\.\d+)?)
+          \s*
+          (?:"|''|’’)?
+        )?
+        \s*
+        (?P<dir>[NSEW])?
+        \s*
+        $
+        "#,
+    )
+    .unwrap();
+
+    let coord = if let Some(caps) = decimal_re.captures(val) {
+        signed_coord(&caps["num"], caps.name("dir").map(|m| m.as_str()))
+    } else if let Some(caps) = dms_re.captures(val) {
+        let negate = caps.name("neg").is_some()
+            || caps
+                .name("dir")
+                .is_some_and(|d| matches!(d.as_str(), "S" | "s" | "W" | "w"));
+        let sec = caps.name("sec").map_or("0", |m| m.as_str());
+        dms2decimal(&caps["deg"], &caps["min"], sec, &negate).and_then(|c| c.parse::<f64>().ok())
+    } else {
+        None
+    };
+
+    coord.filter(|c| c.abs() <= max)
 }
 
 // --------------------------------------------------
@@ -457,7 +1367,8 @@ fn dms2decimal(degree: &str, min: &str, sec: &str, negate: &bool) -> Option<Stri
     if let Ok(degree) = degree.parse::<f64>() {
         if let Ok(min) = min.parse::<f64>() {
             if let Ok(sec) = sec.parse::<f64>() {
-                return Some(format!("{:.5}", degree + (min/60.0) + (sec/3600.0)));
+                let dec = degree + (min / 60.0) + (sec / 3600.0);
+                return Some(format!("{:.5}", if *negate { -dec } else { dec }));
             }
         }
     }
@@ -488,38 +1399,25 @@ fn parse_lat_lon_combined(val: &str) -> Option<(String, String)> {
         $
         ";
 
-    println!("p1 = {}", p1);
-    println!("val = {}", val);
     let re1 = Regex::new(p1).unwrap();
 
-    let (lat, lon) = if let Some(caps) = re1.captures(&val) {
-        println!("caps = {:?}", caps);
-
+    let (lat, lon) = if let Some(caps) = re1.captures(val) {
         let lat1 = if let Some(deg) = caps.name("lat_degrees") {
             if let Some(min) = caps.name("lat_minutes") {
                 if let Some(sec) = caps.name("lat_seconds") {
                     let negate = match caps.name("lat_direction") {
-                        Some(d) => match d.as_str() {
-                            "S" | "s" => true,
-                            _ => false,
-                        }
-                        _ => false
+                        Some(d) => matches!(d.as_str(), "S" | "s"),
+                        _ => false,
                     };
 
-                    dms2decimal(&deg.as_str(), 
-                                &min.as_str(), 
-                                &sec.as_str(), 
-                                &negate)
-                }
-                else {
+                    dms2decimal(deg.as_str(), min.as_str(), sec.as_str(), &negate)
+                } else {
                     None
                 }
-            }
-            else {
+            } else {
                 None
             }
-        }
-        else {
+        } else {
             None
         };
 
@@ -527,65 +1425,53 @@ fn parse_lat_lon_combined(val: &str) -> Option<(String, String)> {
             if let Some(min) = caps.name("lon_minutes") {
                 if let Some(sec) = caps.name("lon_seconds") {
                     let negate = match caps.name("lon_direction") {
-                        Some(d) => match d.as_str() {
-                            "W" | "w" => true,
-                            _ => false,
-                        }
-                        _ => false
+                        Some(d) => matches!(d.as_str(), "W" | "w"),
+                        _ => false,
                     };
-                    dms2decimal(&deg.as_str(), 
-                                &min.as_str(), 
-                                &sec.as_str(),
-                                &negate)
-                }
-                else {
+                    dms2decimal(deg.as_str(), min.as_str(), sec.as_str(), &negate)
+                } else {
                     None
                 }
-            }
-            else {
+            } else {
                 None
             }
-        }
-        else {
+        } else {
             None
         };
 
         (lat1, lon1)
-    }
-    else {
+    } else {
         (None, None)
     };
 
-    println!("LAT = {:?}", lat);
-    println!("LON = {:?}", lon);
-
     match (lat, lon) {
         (Some(n), Some(m)) => Some((n, m)),
         _ => None,
     }
 
-//    let patterns = vec![
-//        r"(?x)
-//        ^
-//        (?:lat:?\s*)?
-//        ($RE{'num'}{'real'})
-//        (?:\s*([NS]))?
-//        (?:_|\s+|\s*,\s*)
-//        (?:long:?\s*)?
-//        ($RE{'num'}{'real'})
-//        (?:\s*([EW]))?
-//        (?:,\s+decimal\s+degrees)?
-//        $
-//        ",
-//    ];
-
-//    for pattern in patterns {
-//        let re = Regex::new(pattern).unwrap();
-//    }
+    //    let patterns = vec![
+    //        r"(?x)
+    //        ^
+    //        (?:lat:?\s*)?
+    //        ($RE{'num'}{'real'})
+    //        (?:\s*([NS]))?
+    //        (?:_|\s+|\s*,\s*)
+    //        (?:long:?\s*)?
+    //        ($RE{'num'}{'real'})
+    //        (?:\s*([EW]))?
+    //        (?:,\s+decimal\s+degrees)?
+    //        $
+    //        ",
+    //    ];
+
+    //    for pattern in patterns {
+    //        let re = Regex::new(pattern).unwrap();
+    //    }
 }
 
 // --------------------------------------------------
-fn parse_datetime(val: &str) -> Option<DateTime<Utc>> {
+/// The date along with how much of it was given, e.g., "month" for "2015-01"
+fn parse_datetime(val: &str) -> Option<(DateTime<Utc>, &'static str)> {
     let patterns = vec![
         // Excel
         r"^(?P<excel>\d{5})$",
@@ -606,7 +1492,7 @@ fn parse_datetime(val: &str) -> Option<DateTime<Utc>> {
           (?P<seconds>\d+)
         )?
         ",
-        // 2017-06-16Z
+        // 2017-06-16Z, 2017-06-16
         r"(?x)
         ^
         (?P<year>\d{4})
@@ -614,7 +1500,7 @@ fn parse_datetime(val: &str) -> Option<DateTime<Utc>> {
         (?P<month>\d{2})
         -
         (?P<day>\d{2})
-        Z
+        Z?
         $
         ",
         // 2017-06-16/2017-07-09
@@ -715,11 +1601,11 @@ fn parse_datetime(val: &str) -> Option<DateTime<Utc>> {
 
     for p in patterns {
         //println!("v = {} p = {}", val, p);
-        let re = Regex::new(&p).unwrap();
-        if let Some(cap) = re.captures(&val) {
+        let re = Regex::new(p).unwrap();
+        if let Some(cap) = re.captures(val) {
             //println!("YAY! {:?}", cap);
             if let Some(dt) = cap_to_dt(&cap) {
-                return Some(dt);
+                return Some((dt, cap_precision(&cap)));
             }
         }
     }
@@ -727,13 +1613,19 @@ fn parse_datetime(val: &str) -> Option<DateTime<Utc>> {
     None
 }
 
+// --------------------------------------------------
+fn cap_precision(cap: &Captures) -> &'static str {
+    ["seconds", "minutes", "hour", "day", "excel"]
+        .iter()
+        .zip(["second", "minute", "hour", "day", "day"].iter())
+        .find(|(name, _)| cap.name(name).is_some())
+        .map_or("month", |(_, precision)| precision)
+}
+
 // --------------------------------------------------
 fn cap_to_int<T: FromStr>(cap: &Captures, name: &str) -> Option<T> {
     match cap.name(name) {
-        Some(val) => match val.as_str().parse::<T>() {
-            Ok(i) => Some(i),
-            _ => None,
-        },
+        Some(val) => val.as_str().parse::<T>().ok(),
         _ => None,
     }
 }
@@ -756,8 +1648,8 @@ fn month_to_int(month_name: &str) -> Option<u32> {
     ];
 
     for (i, month) in months.iter().enumerate() {
-        let re = Regex::new(&month).unwrap();
-        if re.is_match(&month_name) {
+        let re = Regex::new(month).unwrap();
+        if re.is_match(month_name) {
             return Some(i as u32 + 1);
         }
     }
@@ -767,31 +1659,29 @@ fn month_to_int(month_name: &str) -> Option<u32> {
 
 // --------------------------------------------------
 fn cap_to_dt(cap: &Captures) -> Option<DateTime<Utc>> {
-    if let Some(days) = cap_to_int::<i64>(&cap, "excel") {
-        Some(Utc.ymd(1904, 1, 1).and_hms(0, 0, 0) + Duration::days(days))
+    if let Some(days) = cap_to_int::<i64>(cap, "excel") {
+        Utc.with_ymd_and_hms(1904, 1, 1, 0, 0, 0)
+            .single()
+            .map(|dt| dt + Duration::days(days))
     } else {
-        let hour = cap_to_int::<u32>(&cap, "hour").unwrap_or(0);
-        let minutes = cap_to_int::<u32>(&cap, "minutes").unwrap_or(0);
-        let seconds = cap_to_int::<u32>(&cap, "seconds").unwrap_or(0);
-        let day = cap_to_int::<u32>(&cap, "day").unwrap_or(1);
+        let hour = cap_to_int::<u32>(cap, "hour").unwrap_or(0);
+        let minutes = cap_to_int::<u32>(cap, "minutes").unwrap_or(0);
+        let seconds = cap_to_int::<u32>(cap, "seconds").unwrap_or(0);
+        let day = cap_to_int::<u32>(cap, "day").unwrap_or(1);
 
-        match cap_to_int::<i32>(&cap, "year") {
+        match cap_to_int::<i32>(cap, "year") {
             Some(mut year) => {
                 if year < 100 {
                     year += 2000;
                 }
 
-                let maybe_month = cap_to_int::<u32>(&cap, "month").or_else(
-                    || cap.name("month").and_then(|m| month_to_int(m.as_str())),
-                );
+                let maybe_month = cap_to_int::<u32>(cap, "month")
+                    .or_else(|| cap.name("month").and_then(|m| month_to_int(m.as_str())));
 
-                match maybe_month {
-                    Some(month) => Some(
-                        Utc.ymd(year, month, day)
-                            .and_hms(hour, minutes, seconds),
-                    ),
-                    _ => None,
-                }
+                maybe_month.and_then(|month| {
+                    Utc.with_ymd_and_hms(year, month, day, hour, minutes, seconds)
+                        .single()
+                })
             }
             _ => None,
         }
@@ -800,6 +1690,25 @@ fn cap_to_dt(cap: &Captures) -> Option<DateTime<Utc>> {
 
 // --------------------------------------------------
 // HERE BE TESTS
+// --------------------------------------------------
+/// The Tara Oceans sample in test/ena-3639.xml, which most tests start from
+#[cfg(test)]
+fn tara_sample() -> Sample {
+    let root = Element::parse(File::open("test/ena-3639.xml").unwrap()).unwrap();
+    parse_xml(root, &[]).unwrap()
+}
+
+// --------------------------------------------------
+#[cfg(test)]
+fn make_attr(tag: &str, value: &str, position: usize) -> Attr {
+    Attr {
+        tag: tag.to_string(),
+        value: value.to_string(),
+        position,
+        ..Default::default()
+    }
+}
+
 // --------------------------------------------------
 #[test]
 fn fails_no_id() {
@@ -816,7 +1725,7 @@ fn fails_no_id() {
     let root = Element::parse(xml.as_bytes()).unwrap();
     println!("{:?}", root);
 
-    let res = parse_xml(root);
+    let res = parse_xml(root, &[]);
     assert!(res.is_err());
 }
 
@@ -842,7 +1751,7 @@ fn fails_no_attributres() {
     let root = Element::parse(xml.as_bytes()).unwrap();
     println!("{:?}", root);
 
-    let res = parse_xml(root);
+    let res = parse_xml(root, &[]);
     assert!(res.is_err());
 }
 
@@ -853,6 +1762,7 @@ fn test_parse_datetime() {
         "2012-03-09T08:59",
         "2012-03-09T08:59:03",
         "2017-06-16Z",
+        "2017-06-16",
         "2015-01",
         "2015-01/2015-02",
         "2015-01-03/2015-02-14",
@@ -875,6 +1785,12 @@ fn test_parse_datetime() {
         println!("v = {} : {:?}", v, d);
         assert!(d.is_some());
     }
+
+    let precision = |v| parse_datetime(v).map(|(_, p)| p);
+    assert_eq!(precision("2012-03-09T08:59"), Some("minute"));
+    assert_eq!(precision("2017-06-16Z"), Some("day"));
+    assert_eq!(precision("2015-01/2015-02"), Some("month"));
+    assert_eq!(precision("July of 2011"), Some("month"));
 }
 
 // --------------------------------------------------
@@ -908,16 +1824,112 @@ fn test_parse_depth() {
     assert_eq!(parse_depth("5millimeters"), Some(0.005));
 }
 
+// --------------------------------------------------
+#[test]
+fn test_parse_depth_range() {
+    assert_eq!(parse_depth_range("5 m"), Some((5., 5.)));
+    assert_eq!(parse_depth_range("3-7 m"), Some((3., 7.)));
+    assert_eq!(parse_depth_range("0.5 to 1.5"), Some((0.5, 1.5)));
+    assert_eq!(parse_depth_range("10 - 20 cm"), Some((0.1, 0.2)));
+    assert_eq!(parse_depth_range("surface"), None);
+}
+
 // --------------------------------------------------
 #[test]
 fn test_dms2decimal() {
-    assert_eq!(dms2decimal("41", "40", "13.5", &false), Some("41.67042".to_string()));
-    assert_eq!(dms2decimal("2", "48", "0.6", &false), Some("2.80017".to_string()));
+    assert_eq!(
+        dms2decimal("41", "40", "13.5", &false),
+        Some("41.67042".to_string())
+    );
+    assert_eq!(
+        dms2decimal("2", "48", "0.6", &false),
+        Some("2.80017".to_string())
+    );
 }
 
 // --------------------------------------------------
 #[test]
 fn test_parse_lat_lon_combined() {
-    assert_eq!(parse_lat_lon_combined("41º40,13.5''N 2º48'00.6''E"), 
-        Some(("41.67042".to_string(), "2.80017".to_string())));
+    assert_eq!(
+        parse_lat_lon_combined("41º40,13.5''N 2º48'00.6''E"),
+        Some(("41.67042".to_string(), "2.80017".to_string()))
+    );
+}
+
+// --------------------------------------------------
+#[test]
+fn test_expand_id_range() {
+    assert_eq!(expand_id_range("ERP003628"), vec!["ERP003628"]);
+    assert_eq!(
+        expand_id_range("ERP006156-ERP006157"),
+        vec!["ERP006156", "ERP006157"]
+    );
+    assert_eq!(
+        expand_id_range("ERR0099-ERR0101"),
+        vec!["ERR0099", "ERR0100", "ERR0101"]
+    );
+    assert_eq!(expand_id_range("ERP2-SRP3"), vec!["ERP2-SRP3"]);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_parse_lat_lon() {
+    assert_eq!(parse_lat_lon("36.1715 -29.023"), Some((36.1715, -29.023)));
+    assert_eq!(
+        parse_lat_lon("36.1715 N 29.023 W"),
+        Some((36.1715, -29.023))
+    );
+    assert_eq!(
+        parse_lat_lon("lat 36.1715, long -29.023"),
+        Some((36.1715, -29.023))
+    );
+    assert_eq!(
+        parse_lat_lon("41º40,13.5''N 2º48'00.6''E"),
+        Some((41.67042, 2.80017))
+    );
+    assert_eq!(parse_lat_lon("136.1 29.0"), None);
+    assert_eq!(parse_lat_lon("not collected"), None);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_parse_coord() {
+    assert_eq!(parse_coord("36.1715", 90.), Some(36.1715));
+    assert_eq!(parse_coord("29.023 W", 180.), Some(-29.023));
+    assert_eq!(parse_coord("41º40'13.5''N", 90.), Some(41.67042));
+    assert_eq!(parse_coord("-2°48'", 180.), Some(-2.8));
+    assert_eq!(parse_coord("95.0", 90.), None);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_get_attributes() {
+    let xml = r#"
+    <SAMPLE accession="ERS1">
+         <IDENTIFIERS><PRIMARY_ID>ERS1</PRIMARY_ID></IDENTIFIERS>
+         <SAMPLE_ATTRIBUTES>
+              <SAMPLE_ATTRIBUTE><TAG>depth</TAG><VALUE>5</VALUE><UNITS>m</UNITS></SAMPLE_ATTRIBUTE>
+              <SAMPLE_ATTRIBUTE><TAG>ENA-CHECKLIST</TAG><VALUE>ERC000011</VALUE></SAMPLE_ATTRIBUTE>
+              <SAMPLE_ATTRIBUTE><TAG>sample comment</TAG></SAMPLE_ATTRIBUTE>
+         </SAMPLE_ATTRIBUTES>
+    </SAMPLE>
+    "#;
+
+    let sample = parse_xml(Element::parse(xml.as_bytes()).unwrap(), &[]).unwrap();
+    let tags: Vec<(&str, &str, usize)> = sample
+        .attributes
+        .iter()
+        .map(|a| (a.tag.as_str(), a.value.as_str(), a.position))
+        .collect();
+    assert_eq!(tags, vec![("depth", "5", 1), ("sample comment", "", 3)]);
+    assert_eq!(sample.ena_attributes.len(), 1);
+    assert_eq!(sample.ena_attributes[0].position, 2);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_get_lat_lon() {
+    let root = Element::parse(File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let attrs = get_attributes(&root, None).unwrap();
+    assert_eq!(get_lat_lon(&attrs), Some((36.1715, -29.023)));
 }
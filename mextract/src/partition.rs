@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use super::export::SampleWriter;
+use super::{MyResult, Sample};
+
+/// Most partitions that are open at once; the least recently written
+/// is closed to make room and reopened for appending if needed again
+const MAX_OPEN_FILES: usize = 64;
+
+/// Name of the partition for samples without a study, taxon or size
+/// fraction
+const UNASSIGNED: &str = "unassigned";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartitionBy {
+    Study,
+    Taxon,
+    SizeFraction,
+}
+
+/// Opens the writer for a partition file, writing the header if true
+pub type OpenPartition = Box<dyn Fn(Box<dyn Write>, bool) -> MyResult<Box<dyn SampleWriter>>>;
+
+/// Writes each sample to "<dir>/<key>.<ext>" for each of its study or
+/// taxon keys in a single pass
+pub struct PartitionWriter {
+    dir: PathBuf,
+    ext: String,
+    by: PartitionBy,
+    primary_study: bool,
+    open_partition: OpenPartition,
+    open: HashMap<String, (Box<dyn SampleWriter>, usize)>,
+    created: HashSet<String>,
+    clock: usize,
+}
+
+impl PartitionWriter {
+    pub fn new(
+        dir: &str,
+        ext: &str,
+        by: PartitionBy,
+        primary_study: bool,
+        open_partition: OpenPartition,
+    ) -> PartitionWriter {
+        PartitionWriter {
+            dir: PathBuf::from(dir),
+            ext: ext.to_string(),
+            by,
+            primary_study,
+            open_partition,
+            open: HashMap::new(),
+            created: HashSet::new(),
+            clock: 0,
+        }
+    }
+
+    fn writer_for(&mut self, key: &str) -> MyResult<&mut Box<dyn SampleWriter>> {
+        self.clock += 1;
+        if !self.open.contains_key(key) {
+            if self.open.len() >= MAX_OPEN_FILES {
+                let oldest = self
+                    .open
+                    .iter()
+                    .min_by_key(|(_, (_, used))| *used)
+                    .map(|(k, _)| k.to_string());
+                if let Some((mut writer, _)) = oldest.and_then(|k| self.open.remove(&k)) {
+                    writer.finish()?;
+                }
+            }
+
+            // Truncated the first time, appended to thereafter
+            let is_new = self.created.insert(key.to_string());
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(is_new)
+                .append(!is_new)
+                .open(self.dir.join(format!("{}.{}", key, self.ext)))?;
+            let writer = (self.open_partition)(Box::new(BufWriter::new(file)), is_new)?;
+            self.open.insert(key.to_string(), (writer, 0));
+        }
+
+        let clock = self.clock;
+        let entry = self.open.get_mut(key).unwrap();
+        entry.1 = clock;
+        Ok(&mut entry.0)
+    }
+}
+
+impl SampleWriter for PartitionWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        for key in partition_keys(sample, &self.by, self.primary_study) {
+            self.writer_for(&key)?.write(sample)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        for (_, (mut writer, _)) in self.open.drain() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+/// The sample's distinct, file-safe keys, or only the first (primary)
+/// study if asked
+fn partition_keys(sample: &Sample, by: &PartitionBy, primary_study: bool) -> Vec<String> {
+    let mut keys: Vec<String> = vec![];
+    let candidates: Vec<String> = match by {
+        PartitionBy::Study if primary_study => sample.studies.iter().take(1).cloned().collect(),
+        PartitionBy::Study => sample.studies.clone(),
+        PartitionBy::Taxon => sample.taxon_id.iter().cloned().collect(),
+        PartitionBy::SizeFraction => sample.size_fraction.iter().map(|f| f.key()).collect(),
+    };
+
+    for key in candidates {
+        let key: String = key
+            .trim()
+            .chars()
+            .map(|c| match c {
+                'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect();
+        if !key.is_empty() && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    if keys.is_empty() {
+        keys.push(UNASSIGNED.to_string());
+    }
+
+    keys
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_partition_keys() {
+    let sample = Sample {
+        studies: vec![
+            "ERP003628".to_string(),
+            "ERP006156".to_string(),
+            "ERP003628".to_string(),
+        ],
+        taxon_id: Some("408172".to_string()),
+        size_fraction: Some(super::fraction::SizeFraction {
+            lower: Some(0.8),
+            upper: None,
+            source: "TITLE".to_string(),
+        }),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        partition_keys(&sample, &PartitionBy::Study, false),
+        vec!["ERP003628", "ERP006156"]
+    );
+    assert_eq!(
+        partition_keys(&sample, &PartitionBy::Study, true),
+        vec!["ERP003628"]
+    );
+    assert_eq!(
+        partition_keys(&sample, &PartitionBy::Taxon, false),
+        vec!["408172"]
+    );
+    assert_eq!(
+        partition_keys(&sample, &PartitionBy::SizeFraction, false),
+        vec!["gt0.8"]
+    );
+    assert_eq!(
+        partition_keys(&Sample::default(), &PartitionBy::Taxon, false),
+        vec![UNASSIGNED]
+    );
+}
+
+// --------------------------------------------------
+#[test]
+fn test_partition_writer() {
+    struct LineWriter(Box<dyn Write>);
+    impl SampleWriter for LineWriter {
+        fn write(&mut self, sample: &Sample) -> MyResult<()> {
+            writeln!(self.0, "{}", sample.accession)?;
+            Ok(())
+        }
+        fn finish(&mut self) -> MyResult<()> {
+            self.0.flush()?;
+            Ok(())
+        }
+    }
+
+    let dir = ::std::env::temp_dir().join(format!("mextract-part-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let mut writer = PartitionWriter::new(
+        dir.to_str().unwrap(),
+        "txt",
+        PartitionBy::Taxon,
+        false,
+        Box::new(|mut out, header| {
+            if header {
+                writeln!(out, "accession")?;
+            }
+            Ok(Box::new(LineWriter(out)))
+        }),
+    );
+
+    // more taxa than open files, revisiting the first at the end
+    for i in 0..MAX_OPEN_FILES + 2 {
+        writer
+            .write(&Sample {
+                accession: format!("S{}", i),
+                taxon_id: Some(i.to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(writer.open.len() <= MAX_OPEN_FILES);
+    }
+    writer
+        .write(&Sample {
+            accession: "S0b".to_string(),
+            taxon_id: Some("0".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+    writer.finish().unwrap();
+
+    let first = ::std::fs::read_to_string(dir.join("0.txt")).unwrap();
+    assert_eq!(first, "accession\nS0\nS0b\n");
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,203 @@
+use csv::{ReaderBuilder, Writer, WriterBuilder};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::export::SampleWriter;
+use super::{Attr, MyResult, Sample};
+
+/// Keeps the spill files of writers in the same process apart
+static SPILL_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes one column per attribute tag seen anywhere in the input, most
+/// common first. The columns aren't known until every sample has been
+/// read, so samples are spilled to a temporary file and pivoted at the
+/// end; only the tag counts are held in memory.
+pub struct WideWriter {
+    out: Option<Writer<Box<dyn Write>>>,
+    spill_path: PathBuf,
+    spill: Option<Writer<File>>,
+    tag_count: HashMap<String, usize>,
+    num_samples: usize,
+    min_coverage: f64,
+    include_ena: bool,
+    na: String,
+}
+
+impl WideWriter {
+    pub fn new(
+        out: Writer<Box<dyn Write>>,
+        min_coverage: f64,
+        include_ena: bool,
+        na: &str,
+    ) -> MyResult<WideWriter> {
+        let spill_path = ::std::env::temp_dir().join(format!(
+            "mextract-wide-{}-{}.spill",
+            ::std::process::id(),
+            SPILL_NUM.fetch_add(1, Ordering::SeqCst)
+        ));
+        let spill = WriterBuilder::new()
+            .flexible(true)
+            .has_headers(false)
+            .from_path(&spill_path)?;
+
+        Ok(WideWriter {
+            out: Some(out),
+            spill_path,
+            spill: Some(spill),
+            tag_count: HashMap::new(),
+            num_samples: 0,
+            min_coverage,
+            include_ena,
+            na: na.to_string(),
+        })
+    }
+
+    /// Tags meeting the minimum coverage, by descending frequency
+    fn columns(&self) -> Vec<String> {
+        let min_count = self.min_coverage * self.num_samples as f64;
+        let mut tags: Vec<(&String, &usize)> = self
+            .tag_count
+            .iter()
+            .filter(|(_, &count)| count as f64 >= min_count)
+            .collect();
+        tags.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        tags.into_iter().map(|(tag, _)| tag.to_string()).collect()
+    }
+}
+
+impl SampleWriter for WideWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        let mut attrs: Vec<&Attr> = sample.attributes.iter().collect();
+        if self.include_ena {
+            attrs.extend(sample.ena_attributes.iter());
+        }
+
+        // The first value of a repeated tag wins, as with attr:<TAG>
+        let mut record = vec![sample.accession.as_str()];
+        for attr in attrs {
+            if !record[1..].chunks(2).any(|pair| pair[0] == attr.tag) {
+                *self.tag_count.entry(attr.tag.to_string()).or_insert(0) += 1;
+                record.push(&attr.tag);
+                record.push(&attr.value);
+            }
+        }
+
+        if let Some(spill) = self.spill.as_mut() {
+            spill.write_record(&record)?;
+        }
+        self.num_samples += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        if let Some(mut spill) = self.spill.take() {
+            spill.flush()?;
+        }
+
+        let columns = self.columns();
+        let index: HashMap<&str, usize> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, tag)| (tag.as_str(), i))
+            .collect();
+
+        if let Some(mut out) = self.out.take() {
+            let mut header = vec!["accession"];
+            header.extend(columns.iter().map(|c| c.as_str()));
+            out.write_record(&header)?;
+
+            let mut reader = ReaderBuilder::new()
+                .flexible(true)
+                .has_headers(false)
+                .from_path(&self.spill_path)?;
+
+            for record in reader.records() {
+                let record = record?;
+                let mut row = vec![self.na.as_str(); columns.len() + 1];
+                row[0] = &record[0];
+                let fields: Vec<&str> = record.iter().skip(1).collect();
+                for pair in fields.chunks(2) {
+                    if let (Some(i), Some(val)) = (index.get(pair[0]), pair.get(1)) {
+                        row[i + 1] = val;
+                    }
+                }
+                out.write_record(&row)?;
+            }
+            out.flush()?;
+        }
+
+        fs::remove_file(&self.spill_path)?;
+        Ok(())
+    }
+}
+
+/// The spill file goes even when the export stops before finish()
+impl Drop for WideWriter {
+    fn drop(&mut self) {
+        self.spill.take();
+        if self.spill_path.exists() {
+            let _ = fs::remove_file(&self.spill_path);
+        }
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_wide_writer() {
+    let path = ::std::env::temp_dir().join(format!("mextract-wide-{}.csv", ::std::process::id()));
+    let sample = |acc: &str, attrs: &[(&str, &str)]| Sample {
+        accession: acc.to_string(),
+        attributes: attrs
+            .iter()
+            .map(|(tag, value)| Attr {
+                tag: tag.to_string(),
+                value: value.to_string(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    for (min_coverage, expected) in &[
+        (
+            0.,
+            "accession,depth,temp,rare\nS1,5,20,NA\nS2,10,NA,x\nS3,15,22,NA\n",
+        ),
+        (0.5, "accession,depth,temp\nS1,5,20\nS2,10,NA\nS3,15,22\n"),
+    ] {
+        let out: Box<dyn Write> = Box::new(File::create(&path).unwrap());
+        let mut writer =
+            WideWriter::new(Writer::from_writer(out), *min_coverage, false, "NA").unwrap();
+        writer
+            .write(&sample("S1", &[("temp", "20"), ("depth", "5")]))
+            .unwrap();
+        writer
+            .write(&sample(
+                "S2",
+                &[("depth", "10"), ("rare", "x"), ("depth", "11")],
+            ))
+            .unwrap();
+        writer
+            .write(&sample("S3", &[("depth", "15"), ("temp", "22")]))
+            .unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(&fs::read_to_string(&path).unwrap(), expected);
+        assert!(!writer.spill_path.exists());
+    }
+
+    // an export that fails midway leaves no spill behind
+    let out: Box<dyn Write> = Box::new(File::create(&path).unwrap());
+    let mut writer = WideWriter::new(Writer::from_writer(out), 0., false, "NA").unwrap();
+    writer.write(&sample("S1", &[("temp", "20")])).unwrap();
+    let spill_path = writer.spill_path.clone();
+    assert!(spill_path.exists());
+    drop(writer);
+    assert!(!spill_path.exists());
+    fs::remove_file(&path).unwrap();
+}
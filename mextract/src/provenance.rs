@@ -0,0 +1,111 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+
+use super::{find_files, MyResult, RULESET_VERSION};
+
+/// How an output was made, for reproducing it later
+#[derive(Debug)]
+pub struct Provenance {
+    pub version: String,
+    pub ruleset_version: String,
+    pub args: Vec<String>,
+    /// (path, SHA-256) of each input file
+    pub inputs: Vec<(String, String)>,
+    pub timestamp: String,
+}
+
+impl Provenance {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "mextract_version": self.version,
+            "ruleset_version": self.ruleset_version,
+            "args": self.args,
+            "inputs": self
+                .inputs
+                .iter()
+                .map(|(path, sha)| json!({ "path": path, "sha256": sha }))
+                .collect::<Vec<Value>>(),
+            "timestamp": self.timestamp,
+        })
+    }
+
+    /// Key-value pairs, with the inputs as a JSON list
+    pub fn key_values(&self) -> Vec<(String, String)> {
+        let json = self.to_json();
+        vec![
+            ("mextract_version".to_string(), self.version.to_string()),
+            (
+                "ruleset_version".to_string(),
+                self.ruleset_version.to_string(),
+            ),
+            ("args".to_string(), json["args"].to_string()),
+            ("inputs".to_string(), json["inputs"].to_string()),
+            ("timestamp".to_string(), self.timestamp.to_string()),
+        ]
+    }
+
+    /// "# "-prefixed lines, with inputs in sha256sum format
+    pub fn comment_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("# mextract_version: {}", self.version),
+            format!("# ruleset_version: {}", self.ruleset_version),
+            format!("# args: {}", self.args.join(" ")),
+            format!("# timestamp: {}", self.timestamp),
+        ];
+        for (path, sha) in &self.inputs {
+            lines.push(format!("# input: {}  {}", sha, path));
+        }
+        lines
+    }
+}
+
+// --------------------------------------------------
+/// Hashes the input files, which are given as for find_files
+pub fn get_provenance(paths: &[String]) -> MyResult<Provenance> {
+    let mut inputs = vec![];
+    if !paths.is_empty() {
+        for file in find_files(paths)? {
+            let sha = file_sha256(&file)?;
+            inputs.push((file, sha));
+        }
+    }
+
+    Ok(Provenance {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        ruleset_version: RULESET_VERSION.to_string(),
+        args: ::std::env::args().collect(),
+        inputs,
+        timestamp: Utc::now().to_rfc3339(),
+    })
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn file_sha256(path: &str) -> MyResult<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_get_provenance() {
+    let prov = get_provenance(&["test/ena-3639.xml".to_string()]).unwrap();
+    assert_eq!(prov.inputs.len(), 1);
+    assert_eq!(prov.inputs[0].1.len(), 64);
+    assert_eq!(prov.ruleset_version, RULESET_VERSION);
+
+    let lines = prov.comment_lines();
+    assert!(lines.iter().all(|l| l.starts_with("# ")));
+    assert!(lines.last().unwrap().ends_with("  test/ena-3639.xml"));
+
+    let json = prov.to_json();
+    assert_eq!(json["inputs"][0]["sha256"], prov.inputs[0].1);
+    assert!(prov.key_values().iter().any(|(k, _)| k == "inputs"));
+}
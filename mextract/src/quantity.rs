@@ -0,0 +1,631 @@
+use regex::Regex;
+
+use super::{Attr, Inferred, MyResult, Sample, Warning};
+
+/// Measurements and the tags they are submitted under (lowercase, with
+/// "_" read as a space); the first unit is assumed when none is given
+pub const QUANTITY_FIELDS: &[(&str, &[&str], &[&str])] = &[
+    (
+        "temperature",
+        &["°C"],
+        &[
+            "temperature",
+            "temp",
+            "temperature sensor",
+            "water temperature",
+            "water temp",
+            "sea surface temperature",
+        ],
+    ),
+    (
+        "salinity",
+        &["PSU"],
+        &["salinity", "salinity sensor", "sea water salinity"],
+    ),
+    (
+        "oxygen",
+        &["µmol/kg", "µmol/L"],
+        &[
+            "oxygen",
+            "oxygen sensor",
+            "dissolved oxygen",
+            "diss oxygen",
+            "o2",
+        ],
+    ),
+    (
+        "nitrate",
+        &["µmol/kg", "µmol/L"],
+        &["nitrate", "nitrate sensor", "no3"],
+    ),
+    (
+        "phosphate",
+        &["µmol/kg", "µmol/L"],
+        &["phosphate", "phosphate sensor", "po4"],
+    ),
+    (
+        "silicate",
+        &["µmol/kg", "µmol/L"],
+        &["silicate", "silicate sensor", "sio4", "silicic acid"],
+    ),
+    (
+        "chlorophyll",
+        &["mg/m³"],
+        &[
+            "chlorophyll",
+            "chlorophyll sensor",
+            "chlorophyll a",
+            "chl a",
+            "chla",
+        ],
+    ),
+];
+
+/// Canonical unit, scale, offset and the normalized spellings (see
+/// `unit_key`) converted to it as value * scale + offset
+const UNITS: &[(&str, f64, f64, &[&str])] = &[
+    ("m", 1., 0., &["m", "meter", "meters", "metre", "metres"]),
+    (
+        "m",
+        0.01,
+        0.,
+        &[
+            "cm",
+            "centimeter",
+            "centimeters",
+            "centimetre",
+            "centimetres",
+        ],
+    ),
+    (
+        "m",
+        0.001,
+        0.,
+        &[
+            "mm",
+            "millimeter",
+            "millimeters",
+            "millimetre",
+            "millimetres",
+        ],
+    ),
+    (
+        "m",
+        1000.,
+        0.,
+        &["km", "kilometer", "kilometers", "kilometre", "kilometres"],
+    ),
+    (
+        "µm",
+        1.,
+        0.,
+        &[
+            "µm",
+            "micron",
+            "microns",
+            "micrometer",
+            "micrometers",
+            "micrometre",
+            "micrometres",
+        ],
+    ),
+    (
+        "µm",
+        1000.,
+        0.,
+        &[
+            "mm",
+            "millimeter",
+            "millimeters",
+            "millimetre",
+            "millimetres",
+        ],
+    ),
+    (
+        "µm",
+        0.001,
+        0.,
+        &["nm", "nanometer", "nanometers", "nanometre", "nanometres"],
+    ),
+    (
+        "°C",
+        1.,
+        0.,
+        &["°c", "c", "degc", "degreec", "celsius", "degreecelsius"],
+    ),
+    ("°C", 1., -273.15, &["k", "kelvin"]),
+    (
+        "°C",
+        5. / 9.,
+        -160. / 9.,
+        &["°f", "f", "degf", "fahrenheit"],
+    ),
+    (
+        "PSU",
+        1.,
+        0.,
+        &[
+            "psu", "pss", "pss-78", "pss78", "ppt", "‰", "permil", "g/kg",
+        ],
+    ),
+    ("µmol/kg", 1., 0., &["µmol/kg"]),
+    ("µmol/kg", 0.001, 0., &["nmol/kg"]),
+    ("µmol/kg", 1000., 0., &["mmol/kg"]),
+    ("µmol/L", 1., 0., &["µmol/l", "mmol/m3", "µmol/dm3"]),
+    ("µmol/L", 0.001, 0., &["nmol/l"]),
+    ("µmol/L", 1000., 0., &["mmol/l", "mol/m3"]),
+    ("mg/m³", 1., 0., &["mg/m3", "µg/l", "ng/ml", "µg/dm3"]),
+    ("mg/m³", 1000., 0., &["mg/l", "g/m3", "µg/ml"]),
+];
+
+/// Molar concentrations in µmol/L, matched as submitted because lowercase
+/// "µm", "nm" and "mm" are lengths
+const MOLAR_UNITS: &[(&str, f64)] = &[("µM", 1.), ("uM", 1.), ("nM", 0.001), ("mM", 1000.)];
+
+/// Molar masses (g/mol) for reading mg/L, taking nutrients as the ion
+const MOLAR_MASSES: &[(&str, f64)] = &[
+    ("oxygen", 31.998),
+    ("nitrate", 62.004),
+    ("phosphate", 94.971),
+    ("silicate", 92.08),
+];
+
+/// µmol/L in one mL/L of dissolved gas
+const GAS_VOLUMES: &[(&str, f64)] = &[("oxygen", 44.661)];
+
+/// Names of what is measured, dropped from units like "mg Chl/m3"
+const SUBSTANCES: &[&str] = &["chla", "chl-a", "chl", "o2", "no3", "po4", "sio4"];
+
+/// A measurement normalized to a canonical unit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub field: &'static str,
+    pub value: f64,
+    pub unit: &'static str,
+    /// The value and units as submitted
+    pub original: String,
+    pub position: usize,
+}
+
+// --------------------------------------------------
+/// Splits "5.2 µmol/kg" into the number and whatever unit follows it
+pub fn split_number(val: &str) -> Option<(f64, &str)> {
+    let re = Regex::new(
+        r"(?x)
+        ^\s*
+        (?P<num>[-+]?(?:\d+(?:\.\d*)?|\.\d+)(?:[eE][-+]?\d+)?)
+        \s*
+        (?P<unit>.*?)
+        \s*$
+        ",
+    )
+    .unwrap();
+
+    let caps = re.captures(val)?;
+    let num = caps.name("num")?.as_str().parse::<f64>().ok()?;
+    Some((num, caps.name("unit").map_or("", |u| u.as_str())))
+}
+
+// --------------------------------------------------
+/// Converts the value from the unit to the canonical one, if it is a
+/// spelling of that canonical unit or of a multiple of it
+pub fn convert(value: f64, unit: &str, canonical: &str) -> Option<f64> {
+    if canonical == "µmol/L" {
+        let unit = unit.trim().replace('μ', "µ");
+        if let Some((_, scale)) = MOLAR_UNITS.iter().find(|(name, _)| *name == unit) {
+            return Some(value * scale);
+        }
+    }
+
+    let key = unit_key(unit);
+    UNITS
+        .iter()
+        .find(|(to, _, _, names)| *to == canonical && names.contains(&key.as_str()))
+        .map(|(_, scale, offset, _)| value * scale + offset)
+}
+
+// --------------------------------------------------
+/// Finds the measurements among the attributes, reading the unit from
+/// the value or else from UNITS; those that do not parse come back as
+/// warnings
+pub fn get_quantities(attrs: &[Attr]) -> (Vec<Quantity>, Vec<Warning>) {
+    let mut quantities = vec![];
+    let mut warnings = vec![];
+    for attr in attrs.iter().filter(|a| !a.value.trim().is_empty()) {
+        let tag = attr.tag.to_lowercase().replace('_', " ");
+        let (field, units, _) = match QUANTITY_FIELDS
+            .iter()
+            .find(|(_, _, tags)| tags.contains(&tag.as_str()))
+        {
+            Some(found) => found,
+            _ => continue,
+        };
+
+        let original = match &attr.units {
+            Some(units) => format!("{} {}", attr.value.trim(), units.trim()),
+            _ => attr.value.trim().to_string(),
+        };
+
+        let parsed = split_number(&attr.value).and_then(|(num, unit)| {
+            let unit = if unit.is_empty() {
+                attr.units.as_deref().unwrap_or("")
+            } else {
+                unit
+            };
+
+            if unit.is_empty() {
+                Some((num, units[0]))
+            } else {
+                units
+                    .iter()
+                    .find_map(|to| convert(num, unit, to).map(|value| (value, *to)))
+                    .or_else(|| to_molar(field, num, unit))
+            }
+        });
+
+        match parsed {
+            Some((value, unit)) => quantities.push(Quantity {
+                field,
+                value,
+                unit,
+                original,
+                position: attr.position,
+            }),
+            _ => warnings.push(Warning {
+                field: field.to_string(),
+                message: format!("could not parse \"{}\" = \"{}\"", attr.tag, original),
+                position: Some(attr.position),
+            }),
+        }
+    }
+
+    (quantities, warnings)
+}
+
+// --------------------------------------------------
+/// Parses --unit FIELD=UNIT choices, e.g., "oxygen=umol/kg", into the
+/// field and canonical unit
+pub fn get_targets(specs: &[String]) -> MyResult<Vec<(&'static str, &'static str)>> {
+    let mut targets = vec![];
+    for spec in specs {
+        let (name, unit) = match spec.split_once('=') {
+            Some((name, unit)) if !name.is_empty() => (name.trim(), unit.trim()),
+            _ => return Err(From::from(format!("Unit \"{}\" is not FIELD=UNIT", spec))),
+        };
+
+        let (field, units, _) = QUANTITY_FIELDS
+            .iter()
+            .find(|(field, _, _)| *field == name)
+            .ok_or_else(|| {
+                let fields: Vec<&str> = QUANTITY_FIELDS.iter().map(|(f, _, _)| *f).collect();
+                format!(
+                    "Unknown field \"{}\", choose from {}",
+                    name,
+                    fields.join(", ")
+                )
+            })?;
+
+        let key = unit_key(unit);
+        let unit = units.iter().find(|to| unit_key(to) == key).ok_or_else(|| {
+            format!(
+                "Cannot convert {} to \"{}\", choose from {}",
+                field,
+                unit,
+                units.join(", ")
+            )
+        })?;
+
+        targets.push((*field, *unit));
+    }
+
+    Ok(targets)
+}
+
+// --------------------------------------------------
+/// Seawater density (kg/m³) at the surface from the UNESCO 1981 (EOS-80)
+/// equation of state
+pub fn seawater_density(salinity: f64, temp: f64) -> f64 {
+    let t = temp;
+    let rho_w = 999.842594 + 6.793952e-2 * t - 9.095290e-3 * t.powi(2) + 1.001685e-4 * t.powi(3)
+        - 1.120083e-6 * t.powi(4)
+        + 6.536332e-9 * t.powi(5);
+    let a = 8.24493e-1 - 4.0899e-3 * t + 7.6438e-5 * t.powi(2) - 8.2467e-7 * t.powi(3)
+        + 5.3875e-9 * t.powi(4);
+    let b = -5.72466e-3 + 1.0227e-4 * t - 1.6546e-6 * t.powi(2);
+    let c = 4.8314e-4;
+
+    rho_w + a * salinity + b * salinity.powf(1.5) + c * salinity.powi(2)
+}
+
+// --------------------------------------------------
+/// Converts the sample's measurements to the chosen units, using the
+/// density from its own temperature and salinity to go between per-liter
+/// and per-kilogram; values that cannot be converted are flagged
+pub fn normalize(sample: &mut Sample, targets: &[(&'static str, &'static str)]) {
+    if targets.is_empty() {
+        return;
+    }
+
+    let measured = |field: &str| {
+        sample
+            .quantities
+            .iter()
+            .find(|q| q.field == field)
+            .map(|q| q.value)
+    };
+    let density = match (measured("salinity"), measured("temperature")) {
+        (Some(salinity), Some(temp)) => Some(seawater_density(salinity, temp)),
+        _ => None,
+    };
+    let missing: Vec<&str> = ["temperature", "salinity"]
+        .iter()
+        .filter(|field| measured(field).is_none())
+        .cloned()
+        .collect();
+
+    let mut used_density = false;
+    let mut warnings = vec![];
+    for quantity in sample.quantities.iter_mut() {
+        let to = match targets.iter().find(|(field, _)| *field == quantity.field) {
+            Some((_, to)) if *to != quantity.unit => *to,
+            _ => continue,
+        };
+
+        // get_targets offers only per-liter and per-kilogram units, so
+        // every conversion goes through kg/L of seawater
+        let kg_per_l = match density {
+            Some(rho) => rho / 1000.,
+            _ => {
+                warnings.push(Warning {
+                    field: quantity.field.to_string(),
+                    message: format!(
+                        "could not convert {} {} to {}: the seawater density needs {}",
+                        quantity.value,
+                        quantity.unit,
+                        to,
+                        missing.join(" and ")
+                    ),
+                    position: Some(quantity.position),
+                });
+                continue;
+            }
+        };
+
+        quantity.value = match to {
+            "µmol/kg" => quantity.value / kg_per_l,
+            _ => quantity.value * kg_per_l,
+        };
+        quantity.unit = to;
+        used_density = true;
+    }
+
+    if let (true, Some(rho)) = (used_density, density) {
+        sample.inferred.push(Inferred {
+            field: "density".to_string(),
+            value: format!("{:.3} kg/m³", rho),
+            source: "temperature, salinity".to_string(),
+        });
+    }
+    sample.warnings.extend(warnings);
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+/// Reads mg/L (by molar mass) or mL/L (of a gas) as µmol/L
+fn to_molar(field: &str, value: f64, unit: &str) -> Option<(f64, &'static str)> {
+    let lookup = |table: &[(&str, f64)]| table.iter().find(|(f, _)| *f == field).map(|(_, n)| *n);
+
+    // µg/L over g/mol is µmol/L
+    if let (Some(ug_per_l), Some(mass)) = (convert(value, unit, "mg/m³"), lookup(MOLAR_MASSES)) {
+        return Some((ug_per_l / mass, "µmol/L"));
+    }
+
+    match (unit_key(unit).as_str(), lookup(GAS_VOLUMES)) {
+        ("ml/l", Some(umol)) => Some((value * umol, "µmol/L")),
+        _ => None,
+    }
+}
+
+// --------------------------------------------------
+/// Lowercases and spells a unit one way: "ºC" => "°c",
+/// "umol kg-1" => "µmol/kg", "mg Chl/m³" => "mg/m3"; molar units like "µM"
+/// are not spelled here as they are lengths once lowercased
+fn unit_key(unit: &str) -> String {
+    let mut key: String = unit
+        .to_lowercase()
+        .replace(['º', '˚'], "°")
+        .replace('μ', "µ")
+        .replace('³', "3")
+        .replace("^3", "3")
+        .replace("degrees", "degree")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '.')
+        .collect();
+
+    for substance in SUBSTANCES {
+        key = key.replace(substance, "");
+    }
+
+    // "kg-1" => "/kg", "l-1" => "/l", "m-3" => "/m3"
+    for (per, div) in &[
+        ("kg-1", "/kg"),
+        ("l-1", "/l"),
+        ("dm-3", "/dm3"),
+        ("m-3", "/m3"),
+    ] {
+        if key.ends_with(per) && !key.contains('/') {
+            key = format!("{}{}", &key[..key.len() - per.len()], div);
+        }
+    }
+
+    // "u" stands in for "µ" only as the prefix of grams and metres (or
+    // moles): "ug/l", "um", "umol/kg"
+    if key.starts_with("ug") || key.starts_with("um") {
+        key = key.replacen('u', "µ", 1);
+    }
+
+    key
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_convert() {
+    assert_eq!(split_number("5.2 µmol/kg"), Some((5.2, "µmol/kg")));
+    assert_eq!(split_number(".5"), Some((0.5, "")));
+    assert_eq!(split_number("-1.8°C"), Some((-1.8, "°C")));
+    assert_eq!(split_number("abc"), None);
+
+    assert_eq!(convert(17.26, "ºC", "°C"), Some(17.26));
+    assert_eq!(
+        convert(290.15, "K", "°C").map(|c| (c * 100.).round()),
+        Some(1700.)
+    );
+    assert_eq!(convert(212., "°F", "°C").map(|c| c.round()), Some(100.));
+    assert_eq!(convert(5., "umol kg-1", "µmol/kg"), Some(5.));
+    assert_eq!(convert(5., "mmol m-3", "µmol/L"), Some(5.));
+    assert_eq!(convert(0.04, "mg Chl/m3", "mg/m³"), Some(0.04));
+    assert_eq!(convert(40., "ng/mL", "mg/m³"), Some(40.));
+    assert_eq!(convert(5., "cm", "m"), Some(0.05));
+    assert_eq!(convert(0.22, "um", "µm"), Some(0.22));
+    assert_eq!(convert(5., "µmol/L", "µmol/kg"), None);
+
+    // molar units keep their case, so they are not read as lengths
+    assert_eq!(convert(5., "mM", "µmol/L"), Some(5000.));
+    assert_eq!(convert(5., "μM", "µmol/L"), Some(5.));
+    assert_eq!(convert(5., "mm", "µmol/L"), None);
+    assert_eq!(convert(5., "mm", "µm"), Some(5000.));
+    assert_eq!(convert(0.22, "um", "µmol/L"), None);
+    assert_eq!(convert(5., "units", "µmol/L"), None);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_get_quantities() {
+    let sample = super::tara_sample();
+    let found: Vec<(&str, f64, &str)> = sample
+        .quantities
+        .iter()
+        .map(|q| (q.field, q.value, q.unit))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("temperature", 17.260108, "°C"),
+            ("salinity", 36.23775, "PSU"),
+            ("oxygen", 232.053333, "µmol/kg"),
+            ("nitrate", 0.334082, "µmol/L"),
+            ("chlorophyll", 0.040347, "mg/m³"),
+        ]
+    );
+    assert_eq!(sample.quantities[2].original, "232.053333 µmol/kg");
+
+    let attr = |tag: &str, value: &str, units: Option<&str>| Attr {
+        tag: tag.to_string(),
+        value: value.to_string(),
+        units: units.map(|u| u.to_string()),
+        position: 1,
+        ..Default::default()
+    };
+    let (quantities, warnings) = get_quantities(&[
+        attr("water_temp", "61 F", None),
+        attr("salinity", "35", None),
+        attr("temp", "warm", None),
+        attr("oxygen", "5", Some("mL/L")),
+        attr("nitrate", "0.62 mg/L", None),
+        attr("chlorophyll", "5", Some("mL/L")),
+    ]);
+    assert_eq!(quantities.len(), 4);
+    assert_eq!(quantities[0].value.round(), 16.);
+    assert_eq!(quantities[1].unit, "PSU");
+    assert_eq!((quantities[2].value * 10.).round(), 2233.);
+    assert_eq!(quantities[2].unit, "µmol/L");
+    assert_eq!(quantities[3].value.round(), 10.);
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(
+        warnings[1].message,
+        "could not parse \"chlorophyll\" = \"5 mL/L\""
+    );
+
+    // "5 uM" nitrate is a concentration, but a "0.22 um" filter a size
+    let (quantities, warnings) = get_quantities(&[
+        attr("nitrate", "5 uM", None),
+        attr("phosphate", "0.22 um", None),
+    ]);
+    assert_eq!(quantities.len(), 1);
+    assert_eq!((quantities[0].value, quantities[0].unit), (5., "µmol/L"));
+    assert_eq!(warnings[0].field, "phosphate");
+
+    let sample = Sample {
+        attributes: vec![super::make_attr("size_frac", "0.22 um", 1)],
+        ..Default::default()
+    };
+    let (fraction, _) = super::fraction::get_size_fraction(&sample);
+    assert_eq!(fraction.unwrap().lower, Some(0.22));
+}
+
+// --------------------------------------------------
+#[test]
+fn test_seawater_density() {
+    let round = |n: f64| (n * 1000.).round() / 1000.;
+    assert_eq!(round(seawater_density(35., 25.)), 1023.343);
+    assert_eq!(round(seawater_density(0., 5.)), 999.967);
+}
+
+// --------------------------------------------------
+#[test]
+fn test_normalize() {
+    assert!(get_targets(&["oxygen".to_string()]).is_err());
+    assert!(get_targets(&["depth=m".to_string()]).is_err());
+    assert!(get_targets(&["temperature=K".to_string()]).is_err());
+
+    let targets =
+        get_targets(&["nitrate=umol/kg".to_string(), "oxygen=µmol/L".to_string()]).unwrap();
+    assert_eq!(targets, vec![("nitrate", "µmol/kg"), ("oxygen", "µmol/L")]);
+
+    let mut sample = super::tara_sample();
+    normalize(&mut sample, &targets);
+
+    let value = |field: &str| {
+        sample
+            .quantities
+            .iter()
+            .find(|q| q.field == field)
+            .map(|q| ((q.value * 1000.).round() / 1000., q.unit))
+    };
+    assert_eq!(value("nitrate"), Some((0.325, "µmol/kg")));
+    assert_eq!(value("oxygen"), Some((238.18, "µmol/L")));
+    assert_eq!(sample.inferred.last().unwrap().field, "density");
+
+    // No salinity, no density
+    let without = |fields: &[&str]| {
+        let mut sample = super::tara_sample();
+        sample.warnings.clear();
+        sample.quantities.retain(|q| !fields.contains(&q.field));
+        normalize(&mut sample, &targets);
+        sample
+    };
+    let sample = without(&["salinity"]);
+    let nitrate = sample
+        .quantities
+        .iter()
+        .find(|q| q.field == "nitrate")
+        .unwrap();
+    assert_eq!((nitrate.value, nitrate.unit), (0.334082, "µmol/L"));
+    let messages: Vec<&str> = sample.warnings.iter().map(|w| w.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "could not convert 232.053333 µmol/kg to µmol/L: the seawater density needs salinity",
+            "could not convert 0.334082 µmol/L to µmol/kg: the seawater density needs salinity",
+        ]
+    );
+    assert!(sample.inferred.iter().all(|i| i.field != "density"));
+
+    let sample = without(&["salinity", "temperature"]);
+    assert!(sample.warnings[1]
+        .message
+        .ends_with("needs temperature and salinity"));
+}
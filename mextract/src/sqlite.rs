@@ -0,0 +1,321 @@
+use rusqlite::{params, Connection};
+
+use super::export::SampleWriter;
+use super::provenance::Provenance;
+use super::{MyResult, Sample};
+
+const SCHEMA: &str = "
+    create table if not exists samples (
+        accession text primary key,
+        biosample text,
+        title text,
+        taxon_id text,
+        scientific_name text,
+        collection_date text,
+        collection_date_tag text,
+        latitude real,
+        longitude real,
+        depth real,
+        size_fraction_lower real,
+        size_fraction_upper real,
+        checklist text,
+        spot_count integer,
+        base_count integer,
+        first_public text,
+        last_update text
+    );
+
+    create table if not exists identifiers (
+        accession text not null references samples (accession),
+        db text not null,
+        id text not null
+    );
+
+    create table if not exists runs (
+        accession text not null references samples (accession),
+        run text not null,
+        experiment text,
+        library_strategy text,
+        library_source text,
+        platform text,
+        instrument_model text
+    );
+
+    create table if not exists links (
+        accession text not null references samples (accession),
+        db text not null,
+        id text not null
+    );
+
+    create table if not exists attributes (
+        accession text not null references samples (accession),
+        tag text not null,
+        value text,
+        units text
+    );
+
+    create table if not exists quantities (
+        accession text not null references samples (accession),
+        field text not null,
+        value real not null,
+        unit text not null,
+        original text
+    );
+
+    create table if not exists env_terms (
+        accession text not null references samples (accession),
+        field text not null,
+        label text,
+        term_id text
+    );
+
+    create table if not exists provenance (
+        timestamp text not null,
+        mextract_version text not null,
+        ruleset_version text not null,
+        args text not null,
+        inputs text not null
+    );
+
+    create index if not exists identifiers_accession on identifiers (accession);
+    create index if not exists runs_accession on runs (accession);
+    create index if not exists links_accession on links (accession);
+    create index if not exists attributes_accession on attributes (accession);
+    create index if not exists attributes_tag on attributes (tag);
+    create index if not exists quantities_accession on quantities (accession);
+    create index if not exists env_terms_accession on env_terms (accession);
+";
+
+/// Child tables whose rows are replaced when a sample is loaded again
+const CHILD_TABLES: &[&str] = &[
+    "identifiers",
+    "runs",
+    "links",
+    "attributes",
+    "quantities",
+    "env_terms",
+];
+
+/// Loads samples into normalized tables, all in one transaction
+pub struct SqliteWriter {
+    conn: Connection,
+}
+
+impl SqliteWriter {
+    /// Each load adds a provenance row, so the history of a database that
+    /// is loaded repeatedly is kept
+    pub fn open(path: &str, prov: &Provenance) -> MyResult<SqliteWriter> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("begin")?;
+
+        let json = prov.to_json();
+        conn.execute(
+            "insert into provenance
+             (timestamp, mextract_version, ruleset_version, args, inputs)
+             values (?1, ?2, ?3, ?4, ?5)",
+            params![
+                prov.timestamp,
+                prov.version,
+                prov.ruleset_version,
+                json["args"].to_string(),
+                json["inputs"].to_string(),
+            ],
+        )?;
+        Ok(SqliteWriter { conn })
+    }
+}
+
+impl SampleWriter for SqliteWriter {
+    /// Upserts on accession so a re-run replaces rather than duplicates
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        let acc = &sample.accession;
+        let date = sample.collection_date();
+        self.conn
+            .prepare_cached(
+                "insert into samples
+                 (accession, biosample, title, taxon_id, scientific_name,
+                  collection_date, collection_date_tag, latitude, longitude,
+                  depth, size_fraction_lower, size_fraction_upper, checklist,
+                  spot_count, base_count, first_public, last_update)
+                 values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+                         ?13, ?14, ?15, ?16, ?17)
+                 on conflict (accession) do update set
+                 biosample = excluded.biosample,
+                 title = excluded.title,
+                 taxon_id = excluded.taxon_id,
+                 scientific_name = excluded.scientific_name,
+                 collection_date = excluded.collection_date,
+                 collection_date_tag = excluded.collection_date_tag,
+                 latitude = excluded.latitude,
+                 longitude = excluded.longitude,
+                 depth = excluded.depth,
+                 size_fraction_lower = excluded.size_fraction_lower,
+                 size_fraction_upper = excluded.size_fraction_upper,
+                 checklist = excluded.checklist,
+                 spot_count = excluded.spot_count,
+                 base_count = excluded.base_count,
+                 first_public = excluded.first_public,
+                 last_update = excluded.last_update",
+            )?
+            .execute(params![
+                acc,
+                sample.biosample,
+                sample.title,
+                sample.taxon_id,
+                sample.scientific_name,
+                date.map(|d| d.value.format("%Y-%m-%dT%H:%M:%S").to_string()),
+                date.map(|d| d.tag.to_string()),
+                sample.lat_lon.map(|(lat, _)| lat),
+                sample.lat_lon.map(|(_, lon)| lon),
+                sample.depth,
+                sample.size_fraction.as_ref().and_then(|f| f.lower),
+                sample.size_fraction.as_ref().and_then(|f| f.upper),
+                sample.ena.checklist,
+                sample.ena.spot_count.map(|n| n as i64),
+                sample.ena.base_count.map(|n| n as i64),
+                sample.ena.first_public.map(|d| d.to_string()),
+                sample.ena.last_update.map(|d| d.to_string()),
+            ])?;
+
+        for table in CHILD_TABLES {
+            self.conn
+                .prepare_cached(&format!("delete from {} where accession = ?1", table))?
+                .execute(params![acc])?;
+        }
+
+        let mut insert = self
+            .conn
+            .prepare_cached("insert into identifiers (accession, db, id) values (?1, ?2, ?3)")?;
+        for xref in &sample.identifiers {
+            insert.execute(params![acc, xref.db, xref.id])?;
+        }
+
+        let mut insert = self
+            .conn
+            .prepare_cached("insert into links (accession, db, id) values (?1, ?2, ?3)")?;
+        for xref in &sample.links {
+            insert.execute(params![acc, xref.db, xref.id])?;
+        }
+
+        let mut insert = self.conn.prepare_cached(
+            "insert into runs
+             (accession, run, experiment, library_strategy,
+              library_source, platform, instrument_model)
+             values (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for run in &sample.runs {
+            let exp = sample
+                .run_info
+                .iter()
+                .find(|i| &i.run == run)
+                .and_then(|i| i.experiment.as_ref());
+            insert.execute(params![
+                acc,
+                run,
+                exp.map(|e| &e.accession),
+                exp.and_then(|e| e.library_strategy.as_ref()),
+                exp.and_then(|e| e.library_source.as_ref()),
+                exp.and_then(|e| e.platform.as_ref()),
+                exp.and_then(|e| e.instrument_model.as_ref()),
+            ])?;
+        }
+
+        let mut insert = self.conn.prepare_cached(
+            "insert into attributes (accession, tag, value, units) values (?1, ?2, ?3, ?4)",
+        )?;
+        for attr in &sample.attributes {
+            insert.execute(params![acc, attr.tag, attr.value, attr.units])?;
+        }
+
+        let mut insert = self.conn.prepare_cached(
+            "insert into quantities (accession, field, value, unit, original)
+             values (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for quantity in &sample.quantities {
+            insert.execute(params![
+                acc,
+                quantity.field,
+                quantity.value,
+                quantity.unit,
+                quantity.original
+            ])?;
+        }
+
+        let mut insert = self.conn.prepare_cached(
+            "insert into env_terms (accession, field, label, term_id) values (?1, ?2, ?3, ?4)",
+        )?;
+        for term in &sample.env_terms {
+            insert.execute(params![acc, term.field, term.label, term.id])?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        self.conn.execute_batch("commit")?;
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_sqlite_upsert() {
+    let root =
+        ::xmltree::Element::parse(::std::fs::File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let sample = super::parse_xml(root, &[]).unwrap();
+    let path = ::std::env::temp_dir().join(format!("mextract-sqlite-{}.db", ::std::process::id()));
+    let path = path.to_str().unwrap();
+
+    // a database left by an earlier run would hold its provenance too
+    if ::std::path::Path::new(path).exists() {
+        ::std::fs::remove_file(path).unwrap();
+    }
+
+    // loading the same sample twice must not duplicate it
+    for _ in 0..2 {
+        let mut writer =
+            SqliteWriter::open(path, &super::provenance::get_provenance(&[]).unwrap()).unwrap();
+        writer.write(&sample).unwrap();
+        writer.finish().unwrap();
+    }
+
+    let conn = Connection::open(path).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(count("select count(*) from samples"), 1);
+    assert_eq!(count("select count(*) from provenance"), 2);
+    assert_eq!(count("select count(*) from runs"), 4);
+    assert_eq!(count("select count(*) from identifiers"), 3);
+    assert_eq!(
+        count("select count(*) from links where db = 'ENA-STUDY'"),
+        4
+    );
+    assert_eq!(
+        count("select count(*) from attributes where tag = 'Salinity Sensor'"),
+        1
+    );
+
+    let lat: f64 = conn
+        .query_row("select latitude from samples", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(lat, 36.1715);
+
+    let (checklist, spots, lower): (String, i64, f64) = conn
+        .query_row(
+            "select checklist, spot_count, size_fraction_lower from samples",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(checklist, "ERC000030");
+    assert_eq!(spots, 344079827);
+    assert_eq!(lower, 0.8);
+    assert_eq!(
+        count("select count(*) from quantities"),
+        sample.quantities.len() as i64
+    );
+    assert_eq!(count("select count(*) from env_terms"), 3);
+    ::std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,270 @@
+use csv;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use super::decode::LabelDecoder;
+use super::ena::{parse_date, EnaInfo};
+use super::experiment::{Experiment, RunInfo};
+use super::{parse_fields, Attr, MyResult, Sample, Xref};
+
+/// SraRunTable and RunInfo headers for the record itself, keyed by the
+/// lowercase name with spaces and underscores removed; anything else is
+/// kept as an attribute
+const RUN_COLUMNS: &[&str] = &[
+    "run",
+    "experiment",
+    "srasample",
+    "sample",
+    "biosample",
+    "srastudy",
+    "bioproject",
+    "librarystrategy",
+    "assaytype",
+    "librarysource",
+    "libraryselection",
+    "librarylayout",
+    "libraryname",
+    "platform",
+    "instrument",
+    "model",
+    "bases",
+    "bytes",
+    "spots",
+    "spotswithmates",
+    "avgspotlen",
+    "avglength",
+    "sizemb",
+    "insertsize",
+    "insertdev",
+    "downloadpath",
+    "releasedate",
+    "loaddate",
+    "consent",
+    "datastorefiletype",
+    "datastoreprovider",
+    "datastoreregion",
+    "taxid",
+    "organism",
+    "scientificname",
+];
+
+// --------------------------------------------------
+/// Reads an SraRunTable (comma- or tab-separated) or RunInfo CSV, with one
+/// row per run, into one sample per SRA sample (or BioSample) accession
+pub fn read_run_table(
+    file: &str,
+    decoders: &[Box<dyn LabelDecoder>],
+) -> MyResult<Vec<MyResult<Sample>>> {
+    let mut first_line = String::new();
+    BufReader::new(File::open(file)?).read_line(&mut first_line)?;
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(sniff_delimiter(&first_line))
+        .flexible(true)
+        .from_reader(File::open(file)?);
+
+    let headers: Vec<String> = reader.headers()?.iter().map(|h| h.to_string()).collect();
+    let keys: Vec<String> = headers.iter().map(|h| column_key(h)).collect();
+
+    let mut samples: Vec<MyResult<Sample>> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (row_num, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                samples.push(Err(From::from(format!("Row {}: {}", row_num + 1, e))));
+                continue;
+            }
+        };
+        let row: HashMap<&str, &str> = keys
+            .iter()
+            .map(|k| k.as_str())
+            .zip(record.iter().map(|v| v.trim()))
+            .filter(|(_, v)| !v.is_empty())
+            .collect();
+        let get = |names: &[&str]| names.iter().find_map(|n| row.get(n)).map(|v| v.to_string());
+
+        let biosample = get(&["biosample"]);
+        let accession = match get(&["srasample", "sample"]).or_else(|| biosample.clone()) {
+            Some(acc) => acc,
+            _ => {
+                samples.push(Err(From::from(format!(
+                    "Row {} has no SRA Sample or BioSample",
+                    row_num + 1
+                ))));
+                continue;
+            }
+        };
+
+        let i = *index.entry(accession.to_string()).or_insert_with(|| {
+            let attributes = headers
+                .iter()
+                .zip(keys.iter())
+                .zip(record.iter())
+                .enumerate()
+                .filter(|(_, ((_, key), val))| {
+                    !RUN_COLUMNS.contains(&key.as_str()) && !val.trim().is_empty()
+                })
+                .map(|(i, ((tag, _), val))| Attr {
+                    tag: tag.to_string(),
+                    value: val.trim().to_string(),
+                    position: i + 1,
+                    ..Default::default()
+                })
+                .collect();
+
+            let identifiers = [
+                ("SRA", get(&["srasample", "sample"])),
+                ("BioSample", biosample.clone()),
+            ]
+            .iter()
+            .filter_map(|(db, id)| {
+                id.as_ref().map(|id| Xref {
+                    db: db.to_string(),
+                    id: id.to_string(),
+                })
+            })
+            .collect();
+
+            samples.push(Ok(Sample {
+                accession: accession.to_string(),
+                biosample: biosample.clone(),
+                taxon_id: get(&["taxid"]),
+                scientific_name: get(&["organism", "scientificname"]),
+                identifiers,
+                studies: [get(&["srastudy"]), get(&["bioproject"])]
+                    .iter()
+                    .flatten()
+                    .cloned()
+                    .collect(),
+                attributes,
+                ena: EnaInfo {
+                    first_public: get(&["releasedate"]).and_then(|d| parse_date(&d)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }));
+            samples.len() - 1
+        });
+
+        if let (Some(run), Ok(sample)) = (get(&["run"]), &mut samples[i]) {
+            let experiment = get(&["experiment"]).map(|acc| Experiment {
+                accession: acc,
+                sample: Some(accession.to_string()),
+                library_strategy: get(&["librarystrategy", "assaytype"]),
+                library_source: get(&["librarysource"]),
+                library_selection: get(&["libraryselection"]),
+                library_layout: get(&["librarylayout"]),
+                platform: get(&["platform"]),
+                instrument_model: get(&["instrument", "model"]),
+            });
+
+            if let Some(exp) = &experiment {
+                if !sample.experiments.contains(&exp.accession) {
+                    sample.experiments.push(exp.accession.to_string());
+                }
+            }
+            // Counts are per run, so the sample's are the sums
+            let add = |total: Option<u64>, names: &[&str]| match get(names)
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                Some(n) => Some(total.unwrap_or(0) + n),
+                _ => total,
+            };
+            sample.ena.spot_count = add(sample.ena.spot_count, &["spots"]);
+            sample.ena.base_count = add(sample.ena.base_count, &["bases"]);
+            sample.runs.push(run.to_string());
+            sample.run_info.push(RunInfo { run, experiment });
+        }
+    }
+
+    for sample in samples.iter_mut().flatten() {
+        parse_fields(sample, decoders);
+    }
+
+    Ok(samples)
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn sniff_delimiter(header: &str) -> u8 {
+    if header.matches('\t').count() > header.matches(',').count() {
+        b'\t'
+    } else {
+        b','
+    }
+}
+
+// --------------------------------------------------
+fn column_key(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '_')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_sniff_delimiter() {
+    assert_eq!(sniff_delimiter("Run,Assay Type,AvgSpotLen"), b',');
+    assert_eq!(sniff_delimiter("Run\tAssay Type\tlat_lon"), b'\t');
+    assert_eq!(column_key("SRA Study"), "srastudy");
+    assert_eq!(column_key("Library_Layout"), "librarylayout");
+}
+
+// --------------------------------------------------
+#[test]
+fn test_read_run_table() {
+    let samples: Vec<Sample> = read_run_table("test/SraRunTable.txt", &[])
+        .unwrap()
+        .into_iter()
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(samples.len(), 2);
+
+    let sample = &samples[0];
+    assert_eq!(sample.accession, "SRS1234567");
+    assert_eq!(sample.biosample, Some("SAMN04567890".to_string()));
+    assert_eq!(sample.runs, vec!["SRR3187421", "SRR3187422"]);
+    assert_eq!(sample.studies, vec!["SRP070123", "PRJNA312345"]);
+    assert_eq!(
+        sample.scientific_name,
+        Some("marine metagenome".to_string())
+    );
+    assert_eq!(sample.depth, Some(5.));
+    assert_eq!(sample.lat_lon, Some((32.8794, -117.2539)));
+    assert!(sample.dates.iter().any(|d| d.tag_ok));
+    assert!(sample.attributes.iter().any(|a| a.tag == "temp"));
+    assert!(!sample.attributes.iter().any(|a| a.tag == "Bases"));
+
+    let exp = sample.run_info[0].experiment.as_ref().unwrap();
+    assert_eq!(exp.library_strategy, Some("WGS".to_string()));
+    assert_eq!(
+        exp.instrument_model,
+        Some("Illumina HiSeq 2500".to_string())
+    );
+
+    assert_eq!(samples[1].runs, vec!["SRR3187423"]);
+    assert_eq!(sample.ena.first_public, parse_date("2016-03-01"));
+    assert_eq!(samples[1].depth, Some(100.));
+
+    // a row that does not read is an error for that row alone
+    let path = ::std::env::temp_dir().join(format!("mextract-sra-{}.csv", ::std::process::id()));
+    let mut table = ::std::fs::read("test/SraRunTable.txt").unwrap();
+    table.extend_from_slice(b"SRR1,WGS,\xff\xfe,x\n");
+    ::std::fs::write(&path, table).unwrap();
+    let samples = read_run_table(path.to_str().unwrap(), &[]).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+    assert_eq!(samples.len(), 3);
+    assert_eq!(samples.iter().filter(|s| s.is_ok()).count(), 2);
+    assert!(samples[2]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .starts_with("Row 4:"));
+}
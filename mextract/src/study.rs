@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use xmltree::Element;
+
+use super::{get_child_text, read_sets, set_members, MyResult, Sample, Warning};
+
+/// Title, abstract and submitter of a STUDY or PROJECT
+#[derive(Debug, Clone, Default)]
+pub struct Study {
+    pub accession: String,
+    pub bioproject: Option<String>,
+    pub title: Option<String>,
+    pub study_abstract: Option<String>,
+    pub center: Option<String>,
+}
+
+// --------------------------------------------------
+/// Reads STUDY_SET and PROJECT_SET XML, indexed by both the study (ERP)
+/// and BioProject (PRJ) accessions
+pub fn read_studies(paths: &[String]) -> MyResult<HashMap<String, Study>> {
+    let mut studies = HashMap::new();
+    for root in read_sets(paths)? {
+        let members = set_members(&root, "STUDY")
+            .into_iter()
+            .chain(set_members(&root, "PROJECT"));
+
+        for elem in members {
+            if let Some(study) = parse_study(elem) {
+                if let Some(prj) = &study.bioproject {
+                    studies.insert(prj.to_string(), study.clone());
+                }
+                studies.insert(study.accession.to_string(), study);
+            }
+        }
+    }
+
+    Ok(studies)
+}
+
+// --------------------------------------------------
+/// Attaches the sample's ENA-STUDY studies and flags missing coordinates
+/// and collection dates along with the study that should explain them
+pub fn join_studies(sample: &mut Sample, studies: &HashMap<String, Study>) {
+    sample.study_info = sample
+        .studies
+        .iter()
+        .filter_map(|acc| studies.get(acc))
+        .cloned()
+        .collect();
+
+    if sample.study_info.is_empty() {
+        return;
+    }
+
+    let context = sample
+        .study_info
+        .iter()
+        .map(|s| match &s.title {
+            Some(title) => format!("{} \"{}\"", s.accession, title),
+            _ => s.accession.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("; ");
+
+    if sample.lat_lon.is_none() {
+        sample.warnings.push(Warning {
+            field: "lat_lon".to_string(),
+            message: format!("missing coordinates, see study {}", context),
+            position: None,
+        });
+    }
+
+    if sample.collection_date().is_none() {
+        sample.warnings.push(Warning {
+            field: "collection_date".to_string(),
+            message: format!("missing collection date, see study {}", context),
+            position: None,
+        });
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn parse_study(elem: &Element) -> Option<Study> {
+    let ids = elem.get_child("IDENTIFIERS");
+    let primary = ids.and_then(|ids| get_child_text(ids, "PRIMARY_ID"));
+    let accession = elem.attributes.get("accession").cloned().or(primary)?;
+
+    // Secondary and external IDs cross-reference the study and project
+    let other_ids: Vec<String> = ids
+        .map(|ids| {
+            ids.children
+                .iter()
+                .filter(|c| c.name == "SECONDARY_ID" || c.name == "EXTERNAL_ID")
+                .filter_map(|c| c.text.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let (accession, bioproject) = if accession.starts_with("PRJ") {
+        let study = other_ids.iter().find(|id| !id.starts_with("PRJ")).cloned();
+        (
+            study.unwrap_or_else(|| accession.to_string()),
+            Some(accession),
+        )
+    } else {
+        let prj = other_ids.iter().find(|id| id.starts_with("PRJ")).cloned();
+        (accession, prj)
+    };
+
+    let (title, study_abstract) = match elem.get_child("DESCRIPTOR") {
+        Some(desc) => (
+            get_child_text(desc, "STUDY_TITLE"),
+            get_child_text(desc, "STUDY_ABSTRACT")
+                .or_else(|| get_child_text(desc, "STUDY_DESCRIPTION")),
+        ),
+        _ => (
+            get_child_text(elem, "TITLE"),
+            get_child_text(elem, "DESCRIPTION"),
+        ),
+    };
+
+    Some(Study {
+        accession,
+        bioproject,
+        title,
+        study_abstract,
+        center: elem.attributes.get("center_name").cloned(),
+    })
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_read_studies() {
+    let studies = read_studies(&[
+        "test/studies.xml".to_string(),
+        "test/projects.xml".to_string(),
+    ])
+    .unwrap();
+
+    let study = &studies["ERP003628"];
+    assert_eq!(study.bioproject, Some("PRJEB4352".to_string()));
+    assert_eq!(study.center, Some("Genoscope".to_string()));
+    assert!(study.study_abstract.is_some());
+    assert_eq!(studies["PRJEB4352"].accession, "ERP003628");
+
+    let project = &studies["ERP006156"];
+    assert_eq!(project.bioproject, Some("PRJEB6606".to_string()));
+    assert_eq!(
+        project.title,
+        Some("Tara Oceans Polar Circle expedition".to_string())
+    );
+}
+
+// --------------------------------------------------
+#[test]
+fn test_join_studies() {
+    let studies = read_studies(&["test/studies.xml".to_string()]).unwrap();
+    let mut sample = Sample {
+        accession: "ERS494529".to_string(),
+        studies: vec!["ERP003628".to_string(), "ERP018626".to_string()],
+        ..Default::default()
+    };
+
+    join_studies(&mut sample, &studies);
+    assert_eq!(sample.study_info.len(), 1);
+    assert_eq!(sample.warnings.len(), 2);
+    assert!(sample.warnings[0].message.contains("ERP003628"));
+}
@@ -0,0 +1,101 @@
+use super::Sample;
+
+/// Taxa to keep, each a taxon ID or a scientific/common name
+#[derive(Debug, Default)]
+pub struct TaxonFilter {
+    pub taxa: Vec<String>,
+    /// Also match any taxon in the sample's resolved lineage
+    pub descendants: bool,
+}
+
+impl TaxonFilter {
+    pub fn is_empty(&self) -> bool {
+        self.taxa.is_empty()
+    }
+
+    /// Matches IDs exactly and names case-insensitively, against the
+    /// lineage too when descendants are wanted
+    pub fn accepts(&self, sample: &Sample) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        self.taxa.iter().any(|taxon| {
+            let taxon = taxon.trim();
+            let lineage = sample.lineage.iter().filter(|_| self.descendants);
+            if taxon.chars().all(|c| c.is_ascii_digit()) {
+                sample.taxon_id.as_ref().is_some_and(|id| id == taxon)
+                    || lineage.map(|t| t.id.to_string()).any(|id| id == taxon)
+            } else {
+                [&sample.scientific_name, &sample.common_name]
+                    .iter()
+                    .filter_map(|name| name.as_ref())
+                    .chain(lineage.map(|t| &t.name))
+                    .any(|name| name.eq_ignore_ascii_case(taxon))
+            }
+        })
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_taxon_filter() {
+    let root =
+        ::xmltree::Element::parse(::std::fs::File::open("test/ena-3639.xml").unwrap()).unwrap();
+    let sample = super::parse_xml(root, &[]).unwrap();
+    assert_eq!(sample.taxon_id, Some("408172".to_string()));
+    assert_eq!(
+        sample.scientific_name,
+        Some("marine metagenome".to_string())
+    );
+
+    let filter = |taxa: &[&str]| TaxonFilter {
+        taxa: taxa.iter().map(|t| t.to_string()).collect(),
+        descendants: false,
+    };
+    assert!(filter(&[]).accepts(&sample));
+    assert!(filter(&["408172"]).accepts(&sample));
+    assert!(filter(&["410658", "Marine Metagenome"]).accepts(&sample));
+    assert!(!filter(&["410658", "soil metagenome"]).accepts(&sample));
+    assert!(!filter(&["4081"]).accepts(&sample));
+}
+
+// --------------------------------------------------
+#[test]
+fn test_taxon_filter_descendants() {
+    use super::taxonomy::Taxon;
+
+    let taxon = |id: u32, name: &str| Taxon {
+        id,
+        parent: 0,
+        rank: "no rank".to_string(),
+        name: name.to_string(),
+    };
+    let sample = Sample {
+        taxon_id: Some("408172".to_string()),
+        lineage: vec![
+            taxon(12908, "unclassified sequences"),
+            taxon(408169, "metagenomes"),
+            taxon(410657, "ecological metagenomes"),
+            taxon(408172, "marine metagenome"),
+        ],
+        ..Default::default()
+    };
+
+    let mut filter = TaxonFilter {
+        taxa: vec!["410657".to_string()],
+        descendants: false,
+    };
+    assert!(!filter.accepts(&sample));
+
+    filter.descendants = true;
+    assert!(filter.accepts(&sample));
+
+    filter.taxa = vec!["Ecological Metagenomes".to_string()];
+    assert!(filter.accepts(&sample));
+
+    filter.taxa = vec!["410656".to_string()];
+    assert!(!filter.accepts(&sample));
+}
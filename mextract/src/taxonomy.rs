@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::{MyResult, Sample, Warning};
+
+/// Ranks offered as export columns
+pub const RANKS: &[&str] = &[
+    "domain",
+    "superkingdom",
+    "kingdom",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "species",
+];
+
+/// NCBI renamed "superkingdom" to "domain" in 2025, so either name finds
+/// the top rank of old and new dumps
+const RANK_ALIASES: &[(&str, &str)] = &[("domain", "superkingdom")];
+
+/// Written next to the dump after the first load, unless placed elsewhere
+const CACHE_FILE: &str = "taxonomy.mextract.bin";
+
+/// Bump when the cache layout changes
+const CACHE_MAGIC: &[u8] = b"MXTAX002";
+
+/// The NCBI taxonomy root, left out of lineages
+const ROOT_ID: u32 = 1;
+
+/// One taxon of an NCBI taxonomy dump
+#[derive(Debug, Clone, PartialEq)]
+pub struct Taxon {
+    pub id: u32,
+    pub parent: u32,
+    pub rank: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Taxonomy {
+    nodes: HashMap<u32, Taxon>,
+}
+
+impl Taxonomy {
+    /// Taxa from the top of the tree down to (and including) the given ID
+    pub fn lineage(&self, id: u32) -> Vec<Taxon> {
+        let mut lineage = vec![];
+        let mut id = id;
+        while let Some(taxon) = self.nodes.get(&id) {
+            if taxon.id == ROOT_ID || lineage.len() > self.nodes.len() {
+                break;
+            }
+            lineage.push(taxon.clone());
+            id = taxon.parent;
+        }
+        lineage.reverse();
+        lineage
+    }
+
+    /// Attaches the lineage of the sample's TAXON_ID, filling in a missing
+    /// scientific name and warning about IDs not in the dump
+    pub fn resolve(&self, sample: &mut Sample) {
+        let id = match &sample.taxon_id {
+            Some(id) => id,
+            _ => return,
+        };
+
+        sample.lineage = id
+            .trim()
+            .parse::<u32>()
+            .map(|id| self.lineage(id))
+            .unwrap_or_default();
+
+        match sample.lineage.last() {
+            Some(taxon) => {
+                if sample.scientific_name.is_none() {
+                    sample.scientific_name = Some(taxon.name.to_string());
+                }
+            }
+            _ => sample.warnings.push(Warning {
+                field: "taxon_id".to_string(),
+                message: format!("taxon \"{}\" is not in the taxonomy", id),
+                position: None,
+            }),
+        }
+    }
+}
+
+// --------------------------------------------------
+/// The taxon of the given rank (or its other name) in a lineage
+pub fn at_rank<'a>(lineage: &'a [Taxon], rank: &str) -> Option<&'a Taxon> {
+    let alias = RANK_ALIASES.iter().find_map(|(new, old)| match rank {
+        r if r == *new => Some(*old),
+        r if r == *old => Some(*new),
+        _ => None,
+    });
+
+    lineage
+        .iter()
+        .find(|t| t.rank == rank)
+        .or_else(|| alias.and_then(|alias| lineage.iter().find(|t| t.rank == alias)))
+}
+
+// --------------------------------------------------
+/// The dump files load reads from the directory, or the cache when the
+/// dump is gone, for provenance
+pub fn source_files(dir: &str, cache: Option<&str>) -> Vec<String> {
+    let dir = Path::new(dir);
+    let dump: Vec<String> = ["nodes.dmp", "names.dmp"]
+        .iter()
+        .map(|file| dir.join(file))
+        .filter(|path| path.is_file())
+        .map(|path| path.display().to_string())
+        .collect();
+
+    if dump.is_empty() {
+        let cache = cache_path(dir, cache);
+        if cache.is_file() {
+            return vec![cache.display().to_string()];
+        }
+    }
+
+    dump
+}
+
+// --------------------------------------------------
+/// Loads names.dmp/nodes.dmp from the directory, preferring a binary cache
+/// that is newer than both and writing one when there is none; the cache
+/// goes in the dump directory unless a path is given, e.g., when the dump
+/// is read-only or shared
+pub fn load(dir: &str, cache: Option<&str>) -> MyResult<Taxonomy> {
+    let dir = Path::new(dir);
+    let nodes_path = dir.join("nodes.dmp");
+    let names_path = dir.join("names.dmp");
+    let cache_path = cache_path(dir, cache);
+
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let cache_fresh = match (
+        modified(&cache_path),
+        modified(&nodes_path),
+        modified(&names_path),
+    ) {
+        (Some(cache), Some(nodes), Some(names)) => cache >= nodes && cache >= names,
+        (Some(_), None, None) => true,
+        _ => false,
+    };
+
+    if cache_fresh {
+        match read_cache(&cache_path) {
+            Ok(taxonomy) => return Ok(taxonomy),
+            Err(e) => eprintln!("Ignoring taxonomy cache {}: {}", cache_path.display(), e),
+        }
+    }
+
+    let taxonomy = read_dump(&nodes_path, &names_path)?;
+    if let Err(e) = write_cache(&taxonomy, &cache_path) {
+        eprintln!(
+            "Cannot write taxonomy cache {}: {}",
+            cache_path.display(),
+            e
+        );
+    }
+
+    Ok(taxonomy)
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn cache_path(dir: &Path, cache: Option<&str>) -> PathBuf {
+    match cache {
+        Some(path) => PathBuf::from(path),
+        _ => dir.join(CACHE_FILE),
+    }
+}
+
+// --------------------------------------------------
+/// Splits a "\t|\t"-delimited dump line
+fn dump_fields(line: &str) -> Vec<&str> {
+    line.trim_end_matches(['\n', '\r'])
+        .trim_end_matches("\t|")
+        .split("\t|\t")
+        .collect()
+}
+
+// --------------------------------------------------
+fn read_dump(nodes_path: &Path, names_path: &Path) -> MyResult<Taxonomy> {
+    let mut nodes = HashMap::new();
+    for line in BufReader::new(File::open(nodes_path)?).lines() {
+        let line = line?;
+        let fields = dump_fields(&line);
+        if fields.len() < 3 {
+            continue;
+        }
+
+        let id: u32 = fields[0].parse()?;
+        nodes.insert(
+            id,
+            Taxon {
+                id,
+                parent: fields[1].parse()?,
+                rank: fields[2].to_string(),
+                name: String::new(),
+            },
+        );
+    }
+
+    for line in BufReader::new(File::open(names_path)?).lines() {
+        let line = line?;
+        let fields = dump_fields(&line);
+        if fields.len() < 4 || fields[3] != "scientific name" {
+            continue;
+        }
+
+        if let Some(taxon) = nodes.get_mut(&fields[0].parse()?) {
+            taxon.name = fields[1].to_string();
+        }
+    }
+
+    Ok(Taxonomy { nodes })
+}
+
+// --------------------------------------------------
+/// Little-endian ID, parent, then u32 length-prefixed rank and name per
+/// taxon
+fn write_cache(taxonomy: &Taxonomy, path: &Path) -> MyResult<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(CACHE_MAGIC)?;
+    out.write_all(&(taxonomy.nodes.len() as u32).to_le_bytes())?;
+    for taxon in taxonomy.nodes.values() {
+        out.write_all(&taxon.id.to_le_bytes())?;
+        out.write_all(&taxon.parent.to_le_bytes())?;
+        for text in &[&taxon.rank, &taxon.name] {
+            let len = u32::try_from(text.len())
+                .map_err(|_| format!("taxon {} has a name too long to cache", taxon.id))?;
+            out.write_all(&len.to_le_bytes())?;
+            out.write_all(text.as_bytes())?;
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+fn read_cache(path: &Path) -> MyResult<Taxonomy> {
+    let mut buf = vec![];
+    File::open(path)?.read_to_end(&mut buf)?;
+    if !buf.starts_with(CACHE_MAGIC) {
+        return Err(From::from("not a taxonomy cache"));
+    }
+
+    let mut pos = CACHE_MAGIC.len();
+    let mut take = |len: usize| -> MyResult<&[u8]> {
+        let bytes = buf.get(pos..pos + len).ok_or("truncated")?;
+        pos += len;
+        Ok(bytes)
+    };
+
+    let u32_at = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let count = u32_at(take(4)?);
+    // each taxon takes at least 16 bytes, so a corrupt count cannot
+    // reserve more than the file could hold
+    let mut nodes = HashMap::with_capacity((count as usize).min(buf.len() / 16));
+    for _ in 0..count {
+        let id = u32_at(take(4)?);
+        let parent = u32_at(take(4)?);
+        let mut texts = vec![];
+        for _ in 0..2 {
+            let len = u32_at(take(4)?) as usize;
+            texts.push(String::from_utf8(take(len)?.to_vec())?);
+        }
+        let name = texts.pop().unwrap_or_default();
+        let rank = texts.pop().unwrap_or_default();
+        nodes.insert(
+            id,
+            Taxon {
+                id,
+                parent,
+                rank,
+                name,
+            },
+        );
+    }
+
+    Ok(Taxonomy { nodes })
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_load() {
+    let dir = ::std::env::temp_dir().join(format!("mextract-taxonomy-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for file in &["nodes.dmp", "names.dmp"] {
+        fs::copy(Path::new("test/taxonomy").join(file), dir.join(file)).unwrap();
+    }
+
+    let dump = load(dir.to_str().unwrap(), None).unwrap();
+    assert!(dir.join(CACHE_FILE).exists());
+    let cached = read_cache(&dir.join(CACHE_FILE)).unwrap();
+    assert_eq!(cached.nodes, dump.nodes);
+
+    // provenance follows the dump, or the cache once the dump is gone
+    let dir_name = dir.to_str().unwrap();
+    assert_eq!(source_files(dir_name, None).len(), 2);
+    fs::remove_file(dir.join("names.dmp")).unwrap();
+    fs::remove_file(dir.join("nodes.dmp")).unwrap();
+    assert_eq!(
+        source_files(dir_name, None),
+        vec![dir.join(CACHE_FILE).display().to_string()]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    // a separate cache leaves the dump directory alone
+    let cache = ::std::env::temp_dir().join(format!("mextract-cache-{}.bin", ::std::process::id()));
+    let cache_name = cache.to_str().unwrap();
+    load("test/taxonomy", Some(cache_name)).unwrap();
+    assert!(cache.exists());
+    assert!(!Path::new("test/taxonomy").join(CACHE_FILE).exists());
+    assert_eq!(read_cache(&cache).unwrap().nodes, dump.nodes);
+    fs::remove_file(&cache).unwrap();
+
+    let names: Vec<String> = dump.lineage(408172).into_iter().map(|t| t.name).collect();
+    assert_eq!(
+        names,
+        vec![
+            "unclassified sequences",
+            "metagenomes",
+            "ecological metagenomes",
+            "marine metagenome"
+        ]
+    );
+    assert_eq!(dump.nodes[&2].name, "Bacteria");
+    assert!(dump.lineage(99).is_empty());
+}
+
+// --------------------------------------------------
+#[test]
+fn test_resolve() {
+    let taxonomy = read_dump(
+        Path::new("test/taxonomy/nodes.dmp"),
+        Path::new("test/taxonomy/names.dmp"),
+    )
+    .unwrap();
+
+    let mut sample = Sample {
+        taxon_id: Some("410658".to_string()),
+        ..Default::default()
+    };
+    taxonomy.resolve(&mut sample);
+    assert_eq!(sample.lineage.len(), 4);
+    assert_eq!(sample.scientific_name, Some("soil metagenome".to_string()));
+
+    sample.taxon_id = Some("99".to_string());
+    taxonomy.resolve(&mut sample);
+    assert!(sample.lineage.is_empty());
+    assert_eq!(sample.warnings[0].field, "taxon_id");
+}
+
+// --------------------------------------------------
+#[test]
+fn test_at_rank() {
+    let taxonomy = read_dump(
+        Path::new("test/taxonomy/nodes.dmp"),
+        Path::new("test/taxonomy/names.dmp"),
+    )
+    .unwrap();
+
+    // the fixture predates the rename, so "domain" falls back
+    let lineage = taxonomy.lineage(2);
+    assert_eq!(at_rank(&lineage, "superkingdom").unwrap().name, "Bacteria");
+    assert_eq!(at_rank(&lineage, "domain").unwrap().name, "Bacteria");
+
+    // and a current dump still fills "superkingdom"
+    let renamed: Vec<Taxon> = lineage
+        .into_iter()
+        .map(|t| Taxon {
+            rank: t.rank.replace("superkingdom", "domain"),
+            ..t
+        })
+        .collect();
+    assert_eq!(at_rank(&renamed, "superkingdom").unwrap().name, "Bacteria");
+    assert!(at_rank(&renamed, "phylum").is_none());
+}
+
+// --------------------------------------------------
+#[test]
+fn test_read_cache_corrupt() {
+    let path =
+        ::std::env::temp_dir().join(format!("mextract-badcache-{}.bin", ::std::process::id()));
+    let mut bytes = CACHE_MAGIC.to_vec();
+    bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&path, &bytes).unwrap();
+    let res = read_cache(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(res.unwrap_err().to_string(), "truncated");
+}
@@ -0,0 +1,422 @@
+use md5;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use super::MyResult;
+
+#[derive(Debug)]
+pub struct VerifyConfig {
+    pub read_dir: String,
+    pub filereport: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok,
+    Unverified,
+    Missing,
+    Corrupt,
+    Extra,
+}
+
+#[derive(Debug)]
+struct FileCheck {
+    sample: Option<String>,
+    run: String,
+    file: String,
+    status: Status,
+    note: String,
+}
+
+#[derive(Debug)]
+struct ExpectedFile {
+    name: String,
+    md5: Option<String>,
+    bytes: Option<u64>,
+}
+
+// --------------------------------------------------
+/// Checks the local reads for each sample's runs, given as (accession,
+/// runs) after the usual joins and run filters
+pub fn run_verify(config: &VerifyConfig, samples: &[(String, Vec<String>)]) -> MyResult<()> {
+    let local = find_reads(Path::new(&config.read_dir))?;
+    let report = match &config.filereport {
+        Some(path) => read_filereport(path)?,
+        _ => HashMap::new(),
+    };
+
+    let checks = check_reads(samples, &local, &report)?;
+
+    println!("sample\trun\tfile\tstatus\tnote");
+    for check in checks.iter() {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            check.sample.as_ref().map_or("-", |s| s.as_str()),
+            check.run,
+            check.file,
+            status_name(&check.status),
+            check.note
+        );
+    }
+
+    for (sample, _) in samples.iter() {
+        let count = |status: Status| {
+            checks
+                .iter()
+                .filter(|c| c.sample.as_ref() == Some(sample) && c.status == status)
+                .count()
+        };
+        eprintln!(
+            "{}: {} ok, {} unverified, {} missing, {} corrupt, {} extra",
+            sample,
+            count(Status::Ok),
+            count(Status::Unverified),
+            count(Status::Missing),
+            count(Status::Corrupt),
+            count(Status::Extra)
+        );
+    }
+
+    let num_bad = checks
+        .iter()
+        .filter(|c| matches!(c.status, Status::Missing | Status::Corrupt | Status::Extra))
+        .count();
+
+    if num_bad > 0 {
+        return Err(From::from(format!(
+            "{} problem{} found with local reads",
+            num_bad,
+            if num_bad == 1 { "" } else { "s" }
+        )));
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn status_name(status: &Status) -> &'static str {
+    match status {
+        Status::Ok => "ok",
+        Status::Unverified => "unverified",
+        Status::Missing => "missing",
+        Status::Corrupt => "corrupt",
+        Status::Extra => "extra",
+    }
+}
+
+// --------------------------------------------------
+/// Maps run accession to the FASTQ files found for it under `dir`
+fn find_reads(dir: &Path) -> MyResult<HashMap<String, Vec<PathBuf>>> {
+    let read_re = Regex::new(r"^(?P<run>[EDS]RR\d+)(?:_\d+)?\.fastq\.gz$").unwrap();
+    let mut reads: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Some(caps) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| read_re.captures(name))
+            {
+                reads
+                    .entry(caps["run"].to_string())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+    }
+
+    for paths in reads.values_mut() {
+        paths.sort();
+    }
+
+    Ok(reads)
+}
+
+// --------------------------------------------------
+/// Reads an ENA filereport with "run_accession" and "fastq_ftp" columns
+/// and, optionally, "fastq_md5" and "fastq_bytes"; multiple files per
+/// run are separated by ";"
+fn read_filereport(path: &str) -> MyResult<HashMap<String, Vec<ExpectedFile>>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: Vec<String> = match lines.next() {
+        Some(line) => line?.split('\t').map(|s| s.trim().to_string()).collect(),
+        _ => return Err(From::from(format!("Empty filereport \"{}\"", path))),
+    };
+
+    let col = |name: &str| header.iter().position(|h| h == name);
+    let (run_col, ftp_col) = match (col("run_accession"), col("fastq_ftp")) {
+        (Some(r), Some(f)) => (r, f),
+        _ => {
+            return Err(From::from(format!(
+                "Filereport \"{}\" needs run_accession and fastq_ftp columns",
+                path
+            )))
+        }
+    };
+    let md5_col = col("fastq_md5");
+    let bytes_col = col("fastq_bytes");
+
+    let mut report: HashMap<String, Vec<ExpectedFile>> = HashMap::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let flds: Vec<&str> = line.split('\t').collect();
+        let split = |i: Option<usize>| -> Vec<&str> {
+            match i.and_then(|i| flds.get(i)) {
+                Some(v) if !v.is_empty() => v.split(';').collect(),
+                _ => vec![],
+            }
+        };
+
+        let run = match flds.get(run_col) {
+            Some(r) => r.to_string(),
+            _ => continue,
+        };
+        let md5s = split(md5_col);
+        let bytes = split(bytes_col);
+        let expected = report.entry(run).or_default();
+        for (i, ftp) in split(Some(ftp_col)).iter().enumerate() {
+            if let Some(name) = ftp.rsplit('/').next() {
+                expected.push(ExpectedFile {
+                    name: name.to_string(),
+                    md5: md5s.get(i).map(|s| s.to_lowercase()),
+                    bytes: bytes.get(i).and_then(|b| b.parse().ok()),
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// --------------------------------------------------
+fn file_md5(path: &Path) -> MyResult<String> {
+    let mut file = File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        context.consume(&buffer[..n]);
+    }
+
+    Ok(format!("{:x}", context.compute()))
+}
+
+// --------------------------------------------------
+fn check_file(path: &Path, expected: &ExpectedFile) -> MyResult<(Status, String)> {
+    let size = fs::metadata(path)?.len();
+    if let Some(bytes) = expected.bytes {
+        if size != bytes {
+            return Ok((Status::Corrupt, format!("size {} expected {}", size, bytes)));
+        }
+    }
+
+    if let Some(md5) = &expected.md5 {
+        let actual = file_md5(path)?;
+        if &actual != md5 {
+            return Ok((Status::Corrupt, format!("md5 {} expected {}", actual, md5)));
+        }
+    }
+
+    match (&expected.bytes, &expected.md5) {
+        (None, None) => Ok((Status::Unverified, "no size or md5".to_string())),
+        _ => Ok((Status::Ok, String::new())),
+    }
+}
+
+// --------------------------------------------------
+fn check_reads(
+    samples: &[(String, Vec<String>)],
+    local: &HashMap<String, Vec<PathBuf>>,
+    report: &HashMap<String, Vec<ExpectedFile>>,
+) -> MyResult<Vec<FileCheck>> {
+    let file_name = |path: &PathBuf| {
+        path.file_name()
+            .map_or(String::new(), |f| f.to_string_lossy().to_string())
+    };
+
+    let mut checks = vec![];
+    let mut known_runs = HashSet::new();
+    for (sample, runs) in samples.iter() {
+        for run in runs.iter() {
+            known_runs.insert(run.to_string());
+            let found = local.get(run).cloned().unwrap_or_default();
+            let mut check = |file: String, status: Status, note: String| {
+                checks.push(FileCheck {
+                    sample: Some(sample.to_string()),
+                    run: run.to_string(),
+                    file,
+                    status,
+                    note,
+                })
+            };
+
+            match report.get(run) {
+                Some(expected) => {
+                    for exp in expected.iter() {
+                        match found.iter().find(|p| file_name(p) == exp.name) {
+                            Some(path) => {
+                                let (status, note) = check_file(path, exp)?;
+                                check(file_name(path), status, note)
+                            }
+                            _ => check(exp.name.to_string(), Status::Missing, String::new()),
+                        }
+                    }
+
+                    for path in found.iter() {
+                        let name = file_name(path);
+                        if !expected.iter().any(|e| e.name == name) {
+                            check(name, Status::Extra, "not listed for this run".to_string());
+                        }
+                    }
+                }
+                _ => {
+                    if found.is_empty() {
+                        check(String::new(), Status::Missing, "no files".to_string());
+                    }
+                    for path in found.iter() {
+                        check(
+                            file_name(path),
+                            Status::Unverified,
+                            "not in filereport".to_string(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let mut unknown: Vec<&String> = local.keys().filter(|r| !known_runs.contains(*r)).collect();
+    unknown.sort();
+    for run in unknown {
+        for path in local[run].iter() {
+            checks.push(FileCheck {
+                sample: None,
+                run: run.to_string(),
+                file: file_name(path),
+                status: Status::Extra,
+                note: "run not linked to any sample".to_string(),
+            });
+        }
+    }
+
+    Ok(checks)
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[cfg(test)]
+fn make_read_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = ::std::env::temp_dir().join(format!("mextract-{}-{}", name, ::std::process::id()));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    for (file, contents) in files {
+        fs::write(dir.join(file), contents).unwrap();
+    }
+    dir
+}
+
+// --------------------------------------------------
+#[test]
+fn test_find_reads() {
+    let dir = make_read_dir(
+        "find",
+        &[
+            ("ERR868459_1.fastq.gz", ""),
+            ("ERR868459_2.fastq.gz", ""),
+            ("ERR1718757.fastq.gz", ""),
+            ("notes.txt", ""),
+        ],
+    );
+
+    let reads = find_reads(&dir).unwrap();
+    assert_eq!(reads.len(), 2);
+    assert_eq!(reads["ERR868459"].len(), 2);
+    assert_eq!(reads["ERR1718757"].len(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// --------------------------------------------------
+#[test]
+fn test_check_reads() {
+    let dir = make_read_dir(
+        "check",
+        &[
+            ("ERR868459_1.fastq.gz", "good"),
+            ("ERR868459_2.fastq.gz", "bad"),
+            ("ERR1718757.fastq.gz", "unlisted"),
+            ("ERR9999999.fastq.gz", "stray"),
+            ("ERR1718758.fastq.gz", "unlisted"),
+        ],
+    );
+
+    let report_file = dir.join("filereport.tsv");
+    fs::write(
+        &report_file,
+        format!(
+            "run_accession\tfastq_ftp\tfastq_md5\tfastq_bytes\n\
+             ERR868459\tftp.sra.ebi.ac.uk/vol1/ERR868459_1.fastq.gz;\
+             ftp.sra.ebi.ac.uk/vol1/ERR868459_2.fastq.gz\t{:x};{:x}\t4;3\n\
+             ERR1719440\tftp.sra.ebi.ac.uk/vol1/ERR1719440.fastq.gz\t\t\n\
+             ERR1718758\t\t\t\n",
+            md5::compute("good"),
+            md5::compute("xxx"),
+        ),
+    )
+    .unwrap();
+
+    let samples = vec![(
+        "ERS494529".to_string(),
+        vec![
+            "ERR868459".to_string(),
+            "ERR1718757".to_string(),
+            "ERR1719440".to_string(),
+            "ERR1756353".to_string(),
+            "ERR1718758".to_string(),
+        ],
+    )];
+    let local = find_reads(&dir).unwrap();
+    let report = read_filereport(report_file.to_str().unwrap()).unwrap();
+    let checks = check_reads(&samples, &local, &report).unwrap();
+    let status = |file: &str| {
+        checks
+            .iter()
+            .find(|c| c.file == file)
+            .map(|c| status_name(&c.status))
+    };
+
+    assert_eq!(status("ERR868459_1.fastq.gz"), Some("ok"));
+    assert_eq!(status("ERR868459_2.fastq.gz"), Some("corrupt"));
+    assert_eq!(status("ERR1718757.fastq.gz"), Some("unverified"));
+    assert_eq!(status("ERR1719440.fastq.gz"), Some("missing"));
+    assert_eq!(status("ERR9999999.fastq.gz"), Some("extra"));
+
+    // in the filereport, but with no fastq files listed
+    let unlisted = checks.iter().find(|c| c.run == "ERR1718758").unwrap();
+    assert_eq!(
+        (status_name(&unlisted.status), unlisted.note.as_str()),
+        ("extra", "not listed for this run")
+    );
+    assert!(checks
+        .iter()
+        .any(|c| c.run == "ERR1756353" && c.status == Status::Missing));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,242 @@
+use rust_xlsxwriter::{Color, Format, Workbook, Worksheet};
+
+use super::export::{column_name, column_value, Column, SampleWriter};
+use super::provenance::Provenance;
+use super::{MyResult, Sample};
+
+/// Fields written as numbers rather than text
+const NUMERIC_FIELDS: &[&str] = &["lat", "latitude", "lon", "longitude", "depth"];
+
+/// Excel's last row (0-based), so 1,048,575 rows below the header
+const MAX_ROW: u32 = 1_048_575;
+
+/// A workbook with sheets of normalized fields, raw attributes and parse
+/// warnings; cells that could not be parsed are highlighted so curators
+/// can find and correct them
+pub struct XlsxWriter {
+    path: String,
+    columns: Vec<Column>,
+    fields: Worksheet,
+    attributes: Worksheet,
+    warnings: Worksheet,
+    provenance: Worksheet,
+    rows: (u32, u32, u32),
+    highlight: Format,
+}
+
+impl XlsxWriter {
+    pub fn open(path: &str, columns: &[Column], prov: &Provenance) -> MyResult<XlsxWriter> {
+        let bold = Format::new().set_bold();
+        let mut sheets = vec![];
+        for (name, header) in &[
+            (
+                "fields",
+                columns.iter().map(column_name).collect::<Vec<String>>(),
+            ),
+            (
+                "attributes",
+                to_strings(&["accession", "tag", "value", "units", "position"]),
+            ),
+            (
+                "warnings",
+                to_strings(&["accession", "field", "tag", "value", "message"]),
+            ),
+            ("provenance", to_strings(&["key", "value"])),
+        ] {
+            let mut sheet = Worksheet::new();
+            sheet.set_name(*name)?;
+            sheet.write_row_with_format(0, 0, header, &bold)?;
+            sheet.set_freeze_panes(1, 0)?;
+            sheets.push(sheet);
+        }
+
+        let mut provenance = sheets.pop().unwrap();
+        for (i, (key, value)) in prov.key_values().iter().enumerate() {
+            provenance.write_string(i as u32 + 1, 0, key)?;
+            provenance.write_string(i as u32 + 1, 1, value)?;
+        }
+        let warnings = sheets.pop().unwrap();
+        let attributes = sheets.pop().unwrap();
+        let fields = sheets.pop().unwrap();
+
+        Ok(XlsxWriter {
+            path: path.to_string(),
+            columns: columns.to_vec(),
+            fields,
+            attributes,
+            warnings,
+            provenance,
+            rows: (0, 0, 0),
+            highlight: Format::new().set_background_color(Color::Yellow),
+        })
+    }
+}
+
+impl SampleWriter for XlsxWriter {
+    fn write(&mut self, sample: &Sample) -> MyResult<()> {
+        let unparsed: Vec<&super::Warning> = sample
+            .warnings
+            .iter()
+            .filter(|w| w.position.is_some())
+            .collect();
+
+        // fail before writing any of the sample rather than partway
+        let num_attrs = sample.attributes.len() + sample.ena_attributes.len();
+        for (sheet, used, adding) in &[
+            ("fields", self.rows.0, 1),
+            ("attributes", self.rows.1, num_attrs),
+            ("warnings", self.rows.2, sample.warnings.len()),
+        ] {
+            if *used as usize + adding > MAX_ROW as usize {
+                return Err(From::from(format!(
+                    "Sample {} would take the {} sheet past Excel's limit of {} rows; \
+                     export fewer samples or use another format",
+                    sample.accession,
+                    sheet,
+                    MAX_ROW + 1
+                )));
+            }
+        }
+
+        self.rows.0 += 1;
+        let row = self.rows.0;
+        for (col, column) in self.columns.iter().enumerate() {
+            let col = col as u16;
+            let flagged = unparsed
+                .iter()
+                .any(|w| Some(w.field.as_str()) == warning_field(column));
+            let value = column_value(sample, column);
+            match (value, column) {
+                (Some(val), Column::Field(name)) if NUMERIC_FIELDS.contains(&name.as_str()) => {
+                    match val.parse::<f64>() {
+                        Ok(num) => self.fields.write_number(row, col, num)?,
+                        _ => self.fields.write_string(row, col, val)?,
+                    };
+                }
+                (Some(val), _) => {
+                    self.fields.write_string(row, col, val)?;
+                }
+                (None, _) if flagged => {
+                    self.fields.write_blank(row, col, &self.highlight)?;
+                }
+                _ => (),
+            }
+        }
+
+        for attr in sample.attributes.iter().chain(sample.ena_attributes.iter()) {
+            self.rows.1 += 1;
+            let row = self.rows.1;
+            self.attributes.write_string(row, 0, &sample.accession)?;
+            self.attributes.write_string(row, 1, &attr.tag)?;
+            if unparsed.iter().any(|w| w.position == Some(attr.position)) {
+                self.attributes
+                    .write_string_with_format(row, 2, &attr.value, &self.highlight)?;
+            } else {
+                self.attributes.write_string(row, 2, &attr.value)?;
+            }
+            if let Some(units) = &attr.units {
+                self.attributes.write_string(row, 3, units)?;
+            }
+            self.attributes.write_number(row, 4, attr.position as f64)?;
+        }
+
+        for warning in &sample.warnings {
+            self.rows.2 += 1;
+            let row = self.rows.2;
+            let attr = sample
+                .attributes
+                .iter()
+                .find(|a| warning.position == Some(a.position));
+            self.warnings.write_string(row, 0, &sample.accession)?;
+            self.warnings.write_string(row, 1, &warning.field)?;
+            if let Some(attr) = attr {
+                self.warnings.write_string(row, 2, &attr.tag)?;
+                self.warnings
+                    .write_string_with_format(row, 3, &attr.value, &self.highlight)?;
+            }
+            self.warnings.write_string(row, 4, &warning.message)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> MyResult<()> {
+        let mut workbook = Workbook::new();
+        for sheet in [
+            &mut self.fields,
+            &mut self.attributes,
+            &mut self.warnings,
+            &mut self.provenance,
+        ] {
+            sheet.autofit();
+            let mut done = Worksheet::new();
+            ::std::mem::swap(sheet, &mut done);
+            workbook.push_worksheet(done);
+        }
+        workbook.save(&self.path)?;
+        Ok(())
+    }
+}
+
+// --------------------------------------------------
+// Private
+// --------------------------------------------------
+fn to_strings(vals: &[&str]) -> Vec<String> {
+    vals.iter().map(|v| v.to_string()).collect()
+}
+
+// --------------------------------------------------
+/// The warning field behind a column, to highlight it when unparsed
+fn warning_field(column: &Column) -> Option<&'static str> {
+    match column {
+        Column::Field(name) => match name.as_str() {
+            "collection_date" | "collection_date_tag" | "collection_date_precision" => {
+                Some("collection_date")
+            }
+            "lat" | "latitude" | "lon" | "longitude" => Some("lat_lon"),
+            "depth" => Some("depth"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// --------------------------------------------------
+// HERE BE TESTS
+// --------------------------------------------------
+#[test]
+fn test_xlsx_writer() {
+    let xml = r#"
+    <SAMPLE accession="ERS1">
+         <IDENTIFIERS><PRIMARY_ID>ERS1</PRIMARY_ID></IDENTIFIERS>
+         <SAMPLE_ATTRIBUTES>
+              <SAMPLE_ATTRIBUTE><TAG>depth</TAG><VALUE>shallow</VALUE></SAMPLE_ATTRIBUTE>
+              <SAMPLE_ATTRIBUTE><TAG>collection_date</TAG><VALUE>2015-06-18</VALUE></SAMPLE_ATTRIBUTE>
+         </SAMPLE_ATTRIBUTES>
+    </SAMPLE>
+    "#;
+    let sample = super::parse_xml(::xmltree::Element::parse(xml.as_bytes()).unwrap(), &[]).unwrap();
+    assert_eq!(sample.warnings.len(), 1);
+    assert_eq!(sample.warnings[0].field, "depth");
+    assert_eq!(sample.warnings[0].position, Some(1));
+
+    let path = ::std::env::temp_dir().join(format!("mextract-{}.xlsx", ::std::process::id()));
+    let path = path.to_str().unwrap();
+    let columns = super::export::parse_columns(super::export::DEFAULT_COLUMNS).unwrap();
+    let prov = super::provenance::get_provenance(&[]).unwrap();
+    let mut writer = XlsxWriter::open(path, &columns, &prov).unwrap();
+    writer.write(&sample).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(writer.rows, (1, 2, 1));
+
+    writer.rows.1 = MAX_ROW - 1;
+    let err = writer.write(&sample).unwrap_err().to_string();
+    assert!(err.contains("attributes sheet past Excel's limit of 1048576 rows"));
+    assert_eq!(writer.rows, (1, MAX_ROW - 1, 1));
+
+    let archive = ::zip::ZipArchive::new(::std::fs::File::open(path).unwrap()).unwrap();
+    assert!(archive
+        .file_names()
+        .any(|n| n == "xl/worksheets/sheet3.xml"));
+    ::std::fs::remove_file(path).unwrap();
+}